@@ -0,0 +1,273 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::{
+    settings::Settings,
+    surface::{spawn_impact_particles, Surface, SurfaceAssets},
+    GameAssets, PlayerCamera,
+};
+
+/// Length of `hit.ogg`, after which a hit voice is free again.
+const HIT_LENGTH: f32 = 3.4;
+/// Hits playing at the same time, the quietest one is cut to make room for a louder one.
+const MAX_HIT_VOICES: usize = 6;
+/// Hits at least this loud duck the music.
+const DUCK_THRESHOLD: f32 = 0.6;
+const DUCK_SECS: f32 = 1.0;
+/// The music is ducked this long when the level is complete.
+const LEVEL_COMPLETE_DUCK_SECS: f32 = 3.0;
+/// Gain of the music while ducked.
+const DUCK_GAIN: f32 = 0.3;
+/// Gain per second the music fades in and out of ducking.
+const DUCK_FADE: f32 = 3.0;
+/// Positions are scaled down before spatialization, the attenuation is in the inverse square of
+/// the distance so this keeps hits within 20 meters at full volume.
+const SPATIAL_SCALE: f32 = 0.05;
+/// Distance between the ears in meters, wider than a head for a clearer panning.
+const EAR_GAP: f32 = 4.0;
+
+struct HitVoice {
+    sink: Handle<SpatialAudioSink>,
+    volume: f32,
+    remaining: f32,
+}
+
+/// Volumes of the music and sound effect channels, and the voices playing on them.
+#[derive(Resource)]
+pub struct AudioMixer {
+    pub master: f32,
+    pub music: f32,
+    pub sfx: f32,
+    pub muted: bool,
+    ost: Handle<AudioSink>,
+    hits: Vec<HitVoice>,
+    /// Rotates through the sounds of the surface banks.
+    next_variant: usize,
+    /// Seconds left before the music comes back up.
+    duck_remaining: f32,
+    duck_gain: f32,
+}
+
+impl Default for AudioMixer {
+    fn default() -> Self {
+        Self {
+            master: 1.0,
+            music: 1.0,
+            sfx: 1.0,
+            muted: false,
+            ost: default(),
+            hits: vec![],
+            next_variant: 0,
+            duck_remaining: 0.0,
+            duck_gain: 1.0,
+        }
+    }
+}
+
+impl AudioMixer {
+    pub fn music_volume(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            self.master * self.music * self.duck_gain
+        }
+    }
+
+    pub fn sfx_volume(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            self.master * self.sfx
+        }
+    }
+
+    /// Lowers the music for `secs`, e.g. under a hit or a fanfare.
+    pub fn duck(&mut self, secs: f32) {
+        self.duck_remaining = self.duck_remaining.max(secs);
+    }
+
+    /// Whether a hit of `volume` can play, cutting the quietest voice if they are all taken.
+    fn claim_hit_voice(&mut self, sinks: &Assets<SpatialAudioSink>, volume: f32) -> bool {
+        if self.hits.len() < MAX_HIT_VOICES {
+            return true;
+        }
+        let quietest = self
+            .hits
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.volume.total_cmp(&b.volume))
+            .map(|(i, voice)| (i, voice.volume));
+        match quietest {
+            Some((i, quietest_volume)) if quietest_volume < volume => {
+                let voice = self.hits.swap_remove(i);
+                if let Some(sink) = sinks.get(&voice.sink) {
+                    sink.stop();
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+pub fn soundtrack(game_assets: Res<GameAssets>, audio: Res<Audio>, mut mixer: ResMut<AudioMixer>) {
+    mixer.ost = audio.play_with_settings(
+        game_assets.ost.clone_weak(),
+        PlaybackSettings {
+            repeat: true,
+            volume: mixer.music_volume(),
+            ..default()
+        },
+    );
+}
+
+/// Also turns the hits already playing up or down, the music follows in [`update_mixer`].
+pub fn apply_audio_settings(
+    settings: Res<Settings>,
+    mut mixer: ResMut<AudioMixer>,
+    sinks: Res<Assets<SpatialAudioSink>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    mixer.master = settings.master_volume;
+    mixer.music = settings.music_volume;
+    mixer.sfx = settings.sfx_volume;
+    mixer.muted = settings.muted;
+    for voice in mixer.hits.iter() {
+        if let Some(sink) = sinks.get(&voice.sink) {
+            sink.set_volume(voice.volume * mixer.sfx_volume());
+        }
+    }
+}
+
+/// Lowers the music as the level complete screen comes up.
+pub fn duck_level_complete(mut mixer: ResMut<AudioMixer>) {
+    mixer.duck(LEVEL_COMPLETE_DUCK_SECS);
+}
+
+/// Frees the finished hit voices and fades the music in and out of ducking.
+pub fn update_mixer(mut mixer: ResMut<AudioMixer>, sinks: Res<Assets<AudioSink>>, time: Res<Time>) {
+    let dt = time.delta_seconds();
+    for voice in mixer.hits.iter_mut() {
+        voice.remaining -= dt;
+    }
+    mixer.hits.retain(|voice| voice.remaining > 0.0);
+
+    mixer.duck_remaining = (mixer.duck_remaining - dt).max(0.0);
+    let target = if mixer.duck_remaining > 0.0 {
+        DUCK_GAIN
+    } else {
+        1.0
+    };
+    let step = DUCK_FADE * dt;
+    mixer.duck_gain += (target - mixer.duck_gain).clamp(-step, step);
+
+    if let Some(sink) = sinks.get(&mixer.ost) {
+        sink.set_volume(mixer.music_volume());
+    }
+}
+
+fn spatial_listener(camera: &GlobalTransform) -> Transform {
+    let (_, rotation, translation) = camera.to_scale_rotation_translation();
+    Transform::from_translation(translation * SPATIAL_SCALE).with_rotation(rotation)
+}
+
+/// World position of the collision, from the contact points if the pair is still touching.
+fn contact_point(
+    rapier_context: &RapierContext,
+    event: &ContactForceEvent,
+    transforms: &Query<&GlobalTransform>,
+) -> Option<Vec3> {
+    let point = rapier_context
+        .contact_pair(event.collider1, event.collider2)
+        .and_then(|pair| {
+            pair.manifolds().find_map(|manifold| {
+                manifold
+                    .solver_contacts()
+                    .next()
+                    .map(|contact| contact.point())
+            })
+        });
+    point.or_else(|| {
+        transforms
+            .get(event.collider1)
+            .ok()
+            .map(|tr| tr.translation())
+    })
+}
+
+/// The surface that was struck: the anvil is metal, so a metal collider only counts if both are.
+fn struck_surface(event: &ContactForceEvent, surface_query: &Query<&Surface>) -> Surface {
+    let surface = |ent| surface_query.get(ent).copied().unwrap_or_default();
+    match surface(event.collider1) {
+        Surface::Metal => surface(event.collider2),
+        surface => surface,
+    }
+}
+
+pub fn hit_events(
+    mut commands: Commands,
+    mut contact_force_events: EventReader<ContactForceEvent>,
+    game_assets: Res<GameAssets>,
+    audio: Res<Audio>,
+    mut mixer: ResMut<AudioMixer>,
+    sinks: Res<Assets<SpatialAudioSink>>,
+    rapier_context: Res<RapierContext>,
+    transforms: Query<&GlobalTransform>,
+    surface_query: Query<&Surface>,
+    surface_assets: Res<SurfaceAssets>,
+    camera_query: Query<&GlobalTransform, With<PlayerCamera>>,
+) {
+    let Ok(camera) = camera_query.get_single() else {
+        return;
+    };
+    let listener = spatial_listener(camera);
+    // the hits still playing follow the player
+    for voice in mixer.hits.iter() {
+        if let Some(sink) = sinks.get(&voice.sink) {
+            sink.set_listener_position(listener, EAR_GAP * SPATIAL_SCALE);
+        }
+    }
+
+    let mut hits: Vec<(f32, Vec3, Surface)> = contact_force_events
+        .iter()
+        .filter_map(|contact_force_event| {
+            let vol = contact_force_event.total_force.length();
+            let vol = (vol / 10000.0).clamp(0.0, 1.0);
+            let surface = struck_surface(contact_force_event, &surface_query);
+            contact_point(&rapier_context, contact_force_event, &transforms)
+                .map(|point| (vol, point, surface))
+        })
+        .collect();
+    // loudest first, they get the voices
+    hits.sort_by(|a, b| b.0.total_cmp(&a.0));
+    for (vol, point, surface) in hits {
+        if !mixer.claim_hit_voice(&sinks, vol) {
+            break;
+        }
+        if vol >= DUCK_THRESHOLD {
+            mixer.duck(DUCK_SECS);
+        }
+        let bank = surface.sound_bank();
+        let speed = bank[mixer.next_variant % bank.len()];
+        mixer.next_variant += 1;
+        let sink = audio.play_spatial_with_settings(
+            game_assets.hit.clone_weak(),
+            PlaybackSettings {
+                volume: vol * mixer.sfx_volume(),
+                speed,
+                ..default()
+            },
+            listener,
+            EAR_GAP * SPATIAL_SCALE,
+            point * SPATIAL_SCALE,
+        );
+        mixer.hits.push(HitVoice {
+            sink,
+            volume: vol,
+            remaining: HIT_LENGTH / speed,
+        });
+        spawn_impact_particles(&mut commands, &surface_assets, surface, point, vol);
+    }
+}
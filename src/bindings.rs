@@ -0,0 +1,233 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::save::{read_ron, write_ron};
+
+const BINDINGS_KEY: &str = "bindings";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Grab,
+    Throw,
+    Recall,
+    Reset,
+    Help,
+}
+
+impl Action {
+    pub const ALL: [Action; 10] = [
+        Action::MoveForward,
+        Action::MoveBack,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Jump,
+        Action::Grab,
+        Action::Throw,
+        Action::Recall,
+        Action::Reset,
+        Action::Help,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Action::MoveForward => "Forward",
+            Action::MoveBack => "Back",
+            Action::MoveLeft => "Left",
+            Action::MoveRight => "Right",
+            Action::Jump => "Jump",
+            Action::Grab => "Grab",
+            Action::Throw => "Throw",
+            Action::Recall => "Recall",
+            Action::Reset => "Reset",
+            Action::Help => "Help",
+        }
+    }
+
+    fn default_key(&self) -> KeyCode {
+        match self {
+            Action::MoveForward => KeyCode::W,
+            Action::MoveBack => KeyCode::S,
+            Action::MoveLeft => KeyCode::A,
+            Action::MoveRight => KeyCode::D,
+            Action::Jump => KeyCode::Space,
+            Action::Grab => KeyCode::E,
+            Action::Throw => KeyCode::Q,
+            Action::Recall => KeyCode::R,
+            Action::Reset => KeyCode::Delete,
+            Action::Help => KeyCode::H,
+        }
+    }
+}
+
+/// The key bound to each [`Action`], persisted in the `bindings` storage entry.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+pub struct Bindings {
+    keys: Vec<(Action, KeyCode)>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self {
+            keys: Action::ALL
+                .iter()
+                .map(|action| (*action, action.default_key()))
+                .collect(),
+        }
+    }
+}
+
+impl Bindings {
+    pub fn key(&self, action: Action) -> KeyCode {
+        self.keys
+            .iter()
+            .find(|(bound, _)| *bound == action)
+            .map(|(_, key)| *key)
+            .unwrap_or_else(|| action.default_key())
+    }
+
+    pub fn key_name(&self, action: Action) -> String {
+        format!("{:?}", self.key(action))
+    }
+
+    /// Binds `key` to `action`, the action it was bound to takes the previous key of `action`.
+    pub fn set(&mut self, action: Action, key: KeyCode) {
+        let previous = self.key(action);
+        let taken_by = Action::ALL
+            .into_iter()
+            .find(|other| *other != action && self.key(*other) == key);
+        if let Some(other) = taken_by {
+            self.bind(other, previous);
+        }
+        self.bind(action, key);
+    }
+
+    fn bind(&mut self, action: Action, key: KeyCode) {
+        self.keys.retain(|(bound, _)| *bound != action);
+        self.keys.push((action, key));
+    }
+}
+
+/// The action waiting for a key press in the controls menu.
+#[derive(Resource, Default, Clone, Debug)]
+pub struct Rebinding(pub Option<Action>);
+
+pub fn load_bindings(mut commands: Commands) {
+    commands.insert_resource(read_ron::<Bindings>(BINDINGS_KEY, "The bindings file"));
+}
+
+pub fn write_bindings(bindings: &Bindings) {
+    write_ron(BINDINGS_KEY, bindings, "the bindings", true);
+}
+
+/// The help box for a level with `objectives` customers.
+pub fn help_text(bindings: &Bindings, objectives: u32) -> String {
+    let key = |action| bindings.key_name(action);
+    let customers = match objectives {
+        1 => "a customer".to_string(),
+        count => format!("{count} customers"),
+    };
+    format!(
+        "\
+Welcome, courier! This is the forge of Anvil Express.
+You have to deliver an anvil to {}.
+
+Move with [{}][{}][{}][{}], jump with [{}].
+Move into walls to climb them and pull yourself up the ledges.
+Pick it up with [{}].
+Hold [{}] to be attracted to the anvil.
+Throw it with [{}] if you are not jumping.
+Stand on it and press [{}] for a boost.
+If you lose it, recall it with [{}] or restart with [{}].
+
+The customers are identified by a blue light.
+Good luck!
+
+[{}] to toggle this box.
+[Escape] to pause.
+",
+        customers,
+        key(Action::MoveForward),
+        key(Action::MoveLeft),
+        key(Action::MoveBack),
+        key(Action::MoveRight),
+        key(Action::Jump),
+        key(Action::Grab),
+        key(Action::Grab),
+        key(Action::Throw),
+        key(Action::Throw),
+        key(Action::Recall),
+        key(Action::Reset),
+        key(Action::Help),
+    )
+}
+
+/// Binds the next key pressed to the action being rebound, [Escape] cancels.
+pub fn rebind_keys(
+    keys: Res<Input<KeyCode>>,
+    mut rebinding: ResMut<Rebinding>,
+    mut bindings: ResMut<Bindings>,
+) {
+    let Some(action) = rebinding.0 else {
+        return;
+    };
+    if let Some(key) = keys.get_just_pressed().next() {
+        if *key != KeyCode::Escape {
+            bindings.set(action, *key);
+            write_bindings(&bindings);
+        }
+        rebinding.0 = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swaps_a_key_already_bound() {
+        let mut bindings = Bindings::default();
+        bindings.set(Action::Jump, KeyCode::E);
+        assert_eq!(bindings.key(Action::Jump), KeyCode::E);
+        assert_eq!(bindings.key(Action::Grab), KeyCode::Space);
+        for action in Action::ALL {
+            let sharing = Action::ALL
+                .into_iter()
+                .filter(|other| bindings.key(*other) == bindings.key(action))
+                .count();
+            assert_eq!(sharing, 1, "{:?} shares its key", action);
+        }
+    }
+
+    #[test]
+    fn rebinding_the_same_key_keeps_it() {
+        let mut bindings = Bindings::default();
+        bindings.set(Action::Grab, KeyCode::E);
+        assert_eq!(bindings.key(Action::Grab), KeyCode::E);
+        assert_eq!(bindings.key(Action::Jump), KeyCode::Space);
+    }
+
+    #[test]
+    fn round_trips_through_ron() {
+        let mut bindings = Bindings::default();
+        bindings.set(Action::Recall, KeyCode::F);
+        bindings.set(Action::Reset, KeyCode::E);
+        // as written by write_bindings
+        let contents = ron::ser::to_string_pretty(&bindings, default()).unwrap();
+        let loaded: Bindings = ron::from_str(&contents).unwrap();
+        for action in Action::ALL {
+            assert_eq!(loaded.key(action), bindings.key(action));
+        }
+    }
+
+    #[test]
+    fn help_counts_the_customers() {
+        let bindings = Bindings::default();
+        assert!(help_text(&bindings, 1).contains("to a customer."));
+        assert!(help_text(&bindings, 4).contains("to 4 customers."));
+    }
+}
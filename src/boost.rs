@@ -0,0 +1,67 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::{bindings::Action, replay::FrameInput, Anvil, Held, Player, Powerup, Progress};
+
+/// Upward speed in m/s of a boost off the anvil, a jump is 7.
+const BOOST_SPEED: f32 = 14.0;
+/// Forward speed in m/s of a boost off the anvil.
+const BOOST_FORWARD_SPEED: f32 = 4.0;
+/// Speed in m/s the anvil is pushed down with by the boost.
+const BOOST_RECOIL: f32 = 3.0;
+/// Speed in m/s above which being yanked through the air by the anvil counts as a launch.
+pub const CATAPULT_SPEED: f32 = 12.0;
+/// Speed in m/s added towards the anvil when the catapult launches, on top of the pull.
+pub const CATAPULT_IMPULSE: f32 = 8.0;
+/// The y of the player's contact normal below this is standing on top.
+const STANDING_MAX_NORMAL_Y: f32 = -0.7;
+
+#[derive(Component)]
+pub struct LaunchText;
+
+/// Throwing while standing on the anvil launches the player off it.
+pub fn anvil_boost(
+    mut player_query: Query<(&mut Player, &Transform, &KinematicCharacterControllerOutput)>,
+    mut anvil_query: Query<(Entity, &mut Velocity), (With<Anvil>, Without<Held>)>,
+    input: Res<FrameInput>,
+    progress: Res<Progress>,
+) {
+    if !input.just_pressed(Action::Throw) {
+        return;
+    }
+    let (Ok((mut player, tr, out)), Ok((anvil_ent, mut anvil_vel))) =
+        (player_query.get_single_mut(), anvil_query.get_single_mut())
+    else {
+        return;
+    };
+    // the normal is the player's, pointing down into the anvil
+    let on_anvil = out.collisions.iter().any(|coll| {
+        coll.entity == anvil_ent
+            && (coll.character_rotation * coll.toi.normal1).y < STANDING_MAX_NORMAL_Y
+    });
+    if !on_anvil || player.launched {
+        return;
+    }
+    let mut speed = BOOST_SPEED;
+    if progress.powerups.contains(&Powerup::Strenght) {
+        speed *= 1.2;
+    }
+    player.velocity = Vec3::Y * speed + tr.forward() * BOOST_FORWARD_SPEED;
+    player.launched = true;
+    anvil_vel.linvel -= Vec3::Y * BOOST_RECOIL;
+}
+
+pub fn update_launch_text(
+    player_query: Query<&Player, Changed<Player>>,
+    mut text_query: Query<&mut Text, With<LaunchText>>,
+) {
+    let Ok(player) = player_query.get_single() else {
+        return;
+    };
+    let value = if player.launched { "Anvil boost!" } else { "" };
+    for mut text in text_query.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.to_string();
+        }
+    }
+}
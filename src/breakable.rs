@@ -0,0 +1,135 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::{replay::FrameInput, Anvil, Progress, Transient};
+
+/// Fragments along each axis of the broken mesh's bounding box.
+const FRAGMENTS_PER_AXIS: usize = 2;
+/// Seconds before the fragments are cleaned up.
+const FRAGMENT_SECS: f32 = 8.0;
+
+/// A scene mesh that shatters when the anvil hits it hard enough.
+#[derive(Component, Clone, Debug)]
+pub struct Breakable {
+    /// Minimum total contact force, in newtons.
+    pub threshold: f32,
+}
+
+#[derive(Component)]
+pub struct Fragment {
+    remaining: f32,
+}
+
+#[derive(Component)]
+pub struct ScoreText;
+
+pub fn score_text(progress: &Progress) -> String {
+    format!("Broken: {}", progress.broken.len())
+}
+
+/// Replaces the breakables hit hard enough by the anvil with dynamic fragments.
+pub fn break_scenery(
+    mut commands: Commands,
+    mut contact_force_events: EventReader<ContactForceEvent>,
+    anvil_query: Query<(), With<Anvil>>,
+    breakable_query: Query<
+        (
+            &Breakable,
+            &GlobalTransform,
+            &Handle<Mesh>,
+            &Handle<StandardMaterial>,
+        ),
+        Without<ColliderDisabled>,
+    >,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut progress: ResMut<Progress>,
+) {
+    let mut broken = vec![];
+    for contact_force_event in contact_force_events.iter() {
+        let (c1, c2) = (contact_force_event.collider1, contact_force_event.collider2);
+        let target = if anvil_query.contains(c1) {
+            c2
+        } else if anvil_query.contains(c2) {
+            c1
+        } else {
+            continue;
+        };
+        if broken.contains(&target) {
+            continue;
+        }
+        let Ok((breakable, gt, mesh, material)) = breakable_query.get(target) else {
+            continue;
+        };
+        if contact_force_event.total_force.length() < breakable.threshold {
+            continue;
+        }
+        let Some(aabb) = meshes.get(mesh).and_then(|mesh| mesh.compute_aabb()) else {
+            continue;
+        };
+        broken.push(target);
+        // hidden rather than despawned, the level scene outlives the resets that restore it
+        commands
+            .entity(target)
+            .insert((ColliderDisabled, Visibility::Hidden));
+        if !progress.broken.contains(&target) {
+            progress.broken.push(target);
+        }
+
+        let (scale, rotation, translation) = gt.to_scale_rotation_translation();
+        let size = Vec3::from(aabb.half_extents) * 2.0 * scale;
+        let cell = size / FRAGMENTS_PER_AXIS as f32;
+        let fragment_mesh = meshes.add(Mesh::from(shape::Box::new(cell.x, cell.y, cell.z)));
+        let center = translation + rotation * (Vec3::from(aabb.center) * scale);
+        for x in 0..FRAGMENTS_PER_AXIS {
+            for y in 0..FRAGMENTS_PER_AXIS {
+                for z in 0..FRAGMENTS_PER_AXIS {
+                    let offset =
+                        (Vec3::new(x as f32, y as f32, z as f32) + 0.5) * cell - size / 2.0;
+                    let offset = rotation * offset;
+                    commands.spawn((
+                        PbrBundle {
+                            mesh: fragment_mesh.clone(),
+                            material: material.clone(),
+                            transform: Transform::from_translation(center + offset)
+                                .with_rotation(rotation),
+                            ..default()
+                        },
+                        RigidBody::Dynamic,
+                        Collider::cuboid(cell.x / 2.0, cell.y / 2.0, cell.z / 2.0),
+                        // fly apart from the center
+                        Velocity::linear(offset.normalize_or_zero() * 2.0),
+                        Fragment {
+                            remaining: FRAGMENT_SECS,
+                        },
+                        Transient::default(),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+pub fn despawn_fragments(
+    mut commands: Commands,
+    mut fragment_query: Query<(Entity, &mut Fragment)>,
+    input: Res<FrameInput>,
+) {
+    for (ent, mut fragment) in fragment_query.iter_mut() {
+        fragment.remaining -= input.dt;
+        if fragment.remaining <= 0.0 {
+            commands.entity(ent).despawn();
+        }
+    }
+}
+
+pub fn update_score_text(
+    mut text_query: Query<&mut Text, With<ScoreText>>,
+    progress: Res<Progress>,
+) {
+    if !progress.is_changed() {
+        return;
+    }
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = score_text(&progress);
+    }
+}
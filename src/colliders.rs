@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use bevy::{
+    asset::HandleId,
+    prelude::*,
+    render::{mesh::VertexAttributeValues, primitives::Aabb},
+};
+use bevy_rapier3d::prelude::*;
+use serde::Deserialize;
+
+/// How the collider of a scene mesh is built.
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ColliderShape {
+    /// Exact, but only fit for static geometry.
+    #[default]
+    Trimesh,
+    ConvexHull,
+    /// Convex parts approximating a concave mesh, slow to build.
+    ConvexDecomposition,
+    /// The bounding box of the mesh.
+    Cuboid,
+    /// A capsule along the longest side of the bounding box.
+    Capsule,
+    /// No collider at all, e.g. for decorations.
+    None,
+}
+
+impl ColliderShape {
+    /// Whether the shape behaves well on a dynamic rigid body.
+    pub fn is_dynamic_friendly(&self) -> bool {
+        !matches!(self, ColliderShape::Trimesh)
+    }
+
+    pub fn build(&self, mesh: &Mesh, aabb: &Aabb) -> Option<Collider> {
+        let center = Vec3::from(aabb.center);
+        let half_extents = Vec3::from(aabb.half_extents);
+        match self {
+            ColliderShape::Trimesh => {
+                Collider::from_bevy_mesh(mesh, &ComputedColliderShape::TriMesh)
+            }
+            ColliderShape::ConvexHull => match mesh.attribute(Mesh::ATTRIBUTE_POSITION)? {
+                VertexAttributeValues::Float32x3(positions) => {
+                    let points: Vec<Vec3> = positions.iter().map(|p| Vec3::from(*p)).collect();
+                    Collider::convex_hull(&points)
+                }
+                _ => None,
+            },
+            ColliderShape::ConvexDecomposition => Collider::from_bevy_mesh(
+                mesh,
+                &ComputedColliderShape::ConvexDecomposition(default()),
+            ),
+            ColliderShape::Cuboid => Some(Collider::compound(vec![(
+                center,
+                Quat::IDENTITY,
+                Collider::cuboid(half_extents.x, half_extents.y, half_extents.z),
+            )])),
+            ColliderShape::Capsule => {
+                let axis = half_extents.max_element();
+                // the two other sides bound the radius
+                let radius = if axis == half_extents.x {
+                    half_extents.y.max(half_extents.z)
+                } else if axis == half_extents.y {
+                    half_extents.x.max(half_extents.z)
+                } else {
+                    half_extents.x.max(half_extents.y)
+                };
+                let half_height = (axis - radius).max(0.0);
+                let capsule = if axis == half_extents.x {
+                    Collider::capsule_x(half_height, radius)
+                } else if axis == half_extents.y {
+                    Collider::capsule_y(half_height, radius)
+                } else {
+                    Collider::capsule_z(half_height, radius)
+                };
+                Some(Collider::compound(vec![(center, Quat::IDENTITY, capsule)]))
+            }
+            ColliderShape::None => None,
+        }
+    }
+}
+
+/// Colliders already built for the meshes of the current level, reused when it's reloaded.
+#[derive(Resource, Default)]
+pub struct ColliderCache {
+    level: Option<usize>,
+    colliders: HashMap<(HandleId, ColliderShape), Option<Collider>>,
+}
+
+impl ColliderCache {
+    /// The collider of `mesh` with `shape`, built on the first call for the level.
+    pub fn get_or_build(
+        &mut self,
+        level: usize,
+        mesh: &Handle<Mesh>,
+        shape: ColliderShape,
+        meshes: &Assets<Mesh>,
+        aabb: &Aabb,
+    ) -> Option<Collider> {
+        if self.level != Some(level) {
+            self.level = Some(level);
+            self.colliders.clear();
+        }
+        self.colliders
+            .entry((mesh.id(), shape))
+            .or_insert_with(|| shape.build(meshes.get(mesh)?, aabb))
+            .clone()
+    }
+}
@@ -0,0 +1,417 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::{
+    bindings::Action, replay::FrameInput, Anvil, Held, Player, PlayerCamera, Powerup, Progress,
+};
+
+/// Downward acceleration in m/s², stronger than the real one for snappier jumps.
+const GRAVITY: f32 = 30.0;
+/// Horizontal acceleration at full input, in m/s².
+const MOVE_ACCELERATION: f32 = 100.0;
+/// Rates per second at which the horizontal velocity decays, the top speed is the acceleration
+/// divided by the rate.
+const GROUND_FRICTION: f32 = 6.3;
+const AIR_FRICTION: f32 = 4.4;
+/// The anvil carries the player further through the air.
+const CARRYING_AIR_FRICTION: f32 = 0.6;
+const LAUNCHED_FRICTION: f32 = 0.6;
+/// Fraction of the acceleration left while carrying the anvil.
+const CARRYING_CONTROL: f32 = 0.5;
+/// Seconds a jump is still allowed after walking off a ledge.
+const COYOTE_TIME: f32 = 0.12;
+/// Seconds a jump pressed just before landing is kept for.
+const JUMP_BUFFER: f32 = 0.15;
+/// Upward speed while climbing, in m/s.
+const CLIMB_SPEED: f32 = 3.0;
+/// Speed in m/s the climber keeps pressing into the wall with, to stay in contact.
+const WALL_STICK_SPEED: f32 = 0.5;
+/// Surfaces whose normal has a lower y are walls.
+const WALL_MAX_NORMAL_Y: f32 = 0.3;
+/// Surfaces whose normal has a higher y can be stood on.
+const FLOOR_MIN_NORMAL_Y: f32 = 0.7;
+/// Radius of the ball cast to find walls and ledges.
+const PROBE_RADIUS: f32 = 0.2;
+/// Height above the feet the walls are probed from, about the chest.
+const WALL_PROBE_HEIGHT: f32 = 1.0;
+/// Distance in meters in front of the player a wall can be climbed from.
+const WALL_REACH: f32 = 0.6;
+/// Half height of the flat box probing for obstacles just over the autostep, to vault them.
+const KNEE_PROBE_HALF_HEIGHT: f32 = 0.05;
+/// Meters climbed up a wall before having to find a ledge or fall, until landing again.
+const CLIMB_HEIGHT: f32 = 1.5;
+/// How far above the feet a ledge can be grabbed, the ledges are probed from there down.
+const LEDGE_REACH: f32 = 2.3;
+/// Ledges lower than this are vaulted over instead of grabbed, the lower ones autostepped.
+const GRAB_MIN_HEIGHT: f32 = 1.2;
+const VAULT_MIN_HEIGHT: f32 = 0.5;
+/// Height of the feet under the ledge while hanging from it.
+const HANG_HEIGHT: f32 = 1.7;
+/// Speed in m/s along the ledge while hanging.
+const SHIMMY_SPEED: f32 = 1.5;
+/// Speed in m/s of the mantle up and over a ledge.
+const MANTLE_SPEED: f32 = 3.0;
+/// How far past the edge the mantle ends.
+const MANTLE_DEPTH: f32 = 0.5;
+/// Seconds before a mantle stuck on the geometry is given up.
+const MANTLE_TIMEOUT: f32 = 1.5;
+/// Seconds after letting go of a ledge before grabbing one again.
+const REGRAB_COOLDOWN: f32 = 0.4;
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControllerState {
+    #[default]
+    Grounded,
+    Airborne,
+    /// Pushing against a wall without the anvil.
+    Climbing,
+    /// Holding on to a ledge, shimmying along it.
+    Hanging,
+    /// Pulling up and over a ledge.
+    Mantling,
+    /// Holding the anvil, on the ground or in the air.
+    Carrying,
+    /// Sent flying by the anvil, until landing.
+    Launched,
+}
+
+/// State of the player's character controller, updated from the last physics step.
+#[derive(Component, Default, Clone, Debug)]
+pub struct Controller {
+    pub state: ControllerState,
+    pub grounded: bool,
+    /// Seconds since the player was last grounded.
+    airborne_secs: f32,
+    /// Seconds left to the last jump press.
+    jump_buffer: f32,
+    /// Outward normal of the wall in front of the player.
+    wall_normal: Option<Vec3>,
+    /// Height the current climb started from, cleared on landing.
+    climb_start: Option<f32>,
+    /// Point on top of the edge of the wall in front of the player.
+    ledge: Option<Vec3>,
+    /// Where the mantle ends, and the seconds it has lasted.
+    mantle: Option<(Vec3, f32)>,
+    regrab_cooldown: f32,
+}
+
+/// Casts `probe` forward from `height` above the feet, for the outward normal of a wall and
+/// the distance to it.
+fn cast_wall(
+    rapier_context: &RapierContext,
+    tr: &Transform,
+    probe: &Collider,
+    height: f32,
+    filter: QueryFilter,
+) -> Option<(Vec3, f32)> {
+    let (_, wall) = rapier_context.cast_shape(
+        tr.translation + Vec3::Y * height,
+        tr.rotation,
+        tr.forward(),
+        probe,
+        WALL_REACH,
+        filter,
+    )?;
+    // the normal is the probe's, in its space and pointing into the wall
+    let wall_normal = -(tr.rotation * wall.normal1);
+    (wall.status != TOIStatus::Penetrating && wall_normal.y.abs() <= WALL_MAX_NORMAL_Y)
+        .then_some((wall_normal, wall.toi))
+}
+
+/// The wall within reach in front of the player, and the ledge on top of it if there's one
+/// within reach too. Only fixed colliders count, so neither the anvil nor the props.
+///
+/// Obstacles too low for the chest probe are found at the knees, they have a ledge to vault
+/// over but no wall to climb.
+fn probe_wall(rapier_context: &RapierContext, tr: &Transform) -> (Option<Vec3>, Option<Vec3>) {
+    let probe = Collider::ball(PROBE_RADIUS);
+    // the held anvil collides with nothing, so the groups leave it out
+    let filter = QueryFilter::only_fixed()
+        .exclude_sensors()
+        .groups(CollisionGroups::new(Group::GROUP_1, Group::ALL));
+    let chest = cast_wall(rapier_context, tr, &probe, WALL_PROBE_HEIGHT, filter);
+    let wall_toi = match chest {
+        Some((_, toi)) => toi,
+        None => {
+            // flat, with its bottom right over the autostep height
+            let knee_probe =
+                Collider::cuboid(PROBE_RADIUS, KNEE_PROBE_HALF_HEIGHT, KNEE_PROBE_HALF_HEIGHT);
+            let knee_height = VAULT_MIN_HEIGHT + KNEE_PROBE_HALF_HEIGHT;
+            match cast_wall(rapier_context, tr, &knee_probe, knee_height, filter) {
+                Some((_, toi)) => toi,
+                None => return (None, None),
+            }
+        }
+    };
+    let wall_normal = chest.map(|(normal, _)| normal);
+    // down from above the edge, just behind the face of the wall
+    let over_edge =
+        tr.translation + Vec3::Y * LEDGE_REACH + tr.forward() * (wall_toi + PROBE_RADIUS * 2.0);
+    let ledge = rapier_context
+        .cast_shape(
+            over_edge,
+            Quat::IDENTITY,
+            Vec3::NEG_Y,
+            &probe,
+            LEDGE_REACH - VAULT_MIN_HEIGHT,
+            filter,
+        )
+        .filter(|(_, top)| {
+            top.status != TOIStatus::Penetrating && -top.normal1.y > FLOOR_MIN_NORMAL_Y
+        })
+        .map(|(_, top)| over_edge - Vec3::Y * (top.toi + PROBE_RADIUS));
+    (wall_normal, ledge)
+}
+
+/// Wished movement direction on the ground plane, at most of length 1.
+fn movement_input(tr: &Transform, input: &FrameInput) -> Vec3 {
+    let mut direction = tr.forward() * input.movement.y + tr.right() * input.movement.x;
+    if input.pressed(Action::MoveForward) {
+        direction += tr.forward();
+    }
+    if input.pressed(Action::MoveBack) {
+        direction -= tr.forward();
+    }
+    if input.pressed(Action::MoveRight) {
+        direction += tr.right();
+    }
+    if input.pressed(Action::MoveLeft) {
+        direction -= tr.right();
+    }
+    if direction.length_squared() > 1.0 {
+        direction = direction.normalize();
+    }
+    direction
+}
+
+pub fn player_look(
+    mut player_query: Query<&mut Transform, (With<Player>, Without<PlayerCamera>)>,
+    mut cam_query: Query<(&PlayerCamera, &mut Transform), Without<Player>>,
+    input: Res<FrameInput>,
+) {
+    let (Ok(mut tr), Ok((cam, mut cam_tr))) =
+        (player_query.get_single_mut(), cam_query.get_single_mut())
+    else {
+        return;
+    };
+    let pitch = cam_tr.rotation.to_euler(EulerRot::XYZ).0;
+    let amt = -input.mouse_delta.y * cam.sensitivity.y + input.look.y * cam.stick_sensitivity.y;
+    if pitch + amt > -PI / 2.0 && pitch + amt < PI / 2.0 {
+        cam_tr.rotate_local_x(amt);
+    }
+    tr.rotate_y(-input.mouse_delta.x * cam.sensitivity.y - input.look.x * cam.stick_sensitivity.x);
+}
+
+pub fn update_controller_state(
+    mut player_query: Query<(
+        &mut Player,
+        &mut Controller,
+        &Transform,
+        &KinematicCharacterControllerOutput,
+    )>,
+    anvil_held_query: Query<(), (With<Anvil>, With<Held>)>,
+    rapier_context: Res<RapierContext>,
+    input: Res<FrameInput>,
+) {
+    let Ok((mut player, mut controller, tr, out)) = player_query.get_single_mut() else {
+        return;
+    };
+    let carrying = !anvil_held_query.is_empty();
+    controller.grounded = out.grounded;
+    if out.grounded {
+        controller.airborne_secs = 0.0;
+        controller.climb_start = None;
+        // still on the anvil the frame of a boost
+        if player.velocity.y <= 0.0 {
+            player.launched = false;
+        }
+    } else {
+        controller.airborne_secs += input.dt;
+    }
+    controller.regrab_cooldown -= input.dt;
+    let (wall_normal, ledge) = probe_wall(&rapier_context, tr);
+    controller.wall_normal = wall_normal;
+    controller.ledge = ledge;
+    let wish = movement_input(tr, &input);
+    let pushing = wish.dot(tr.forward()) > 0.5;
+    let ledge_height = ledge.map(|top| top.y - tr.translation.y);
+
+    match controller.state {
+        ControllerState::Mantling => {
+            let done = controller.mantle.map_or(true, |(target, secs)| {
+                (target - tr.translation).length() < 0.05 || secs > MANTLE_TIMEOUT
+            });
+            if !done {
+                return;
+            }
+            controller.mantle = None;
+        }
+        ControllerState::Hanging => match ledge {
+            Some(top) if pushing || input.just_pressed(Action::Jump) => {
+                controller.start_mantle(top, tr.forward());
+                return;
+            }
+            Some(_) if wish.dot(tr.forward()) >= -0.5 => return,
+            // let go, or the ledge ended
+            _ => controller.regrab_cooldown = REGRAB_COOLDOWN,
+        },
+        _ => {}
+    }
+
+    if let (Some(top), Some(height)) = (ledge, ledge_height) {
+        if pushing && out.grounded && (VAULT_MIN_HEIGHT..GRAB_MIN_HEIGHT).contains(&height) {
+            controller.start_mantle(top, tr.forward());
+            return;
+        }
+        if !out.grounded
+            && controller.regrab_cooldown <= 0.0
+            && (GRAB_MIN_HEIGHT..=LEDGE_REACH).contains(&height)
+        {
+            player.launched = false;
+            player.velocity = Vec3::ZERO;
+            controller.state = ControllerState::Hanging;
+            return;
+        }
+    }
+
+    controller.state = if player.launched {
+        ControllerState::Launched
+    } else if carrying {
+        ControllerState::Carrying
+    } else if controller.wall_normal.is_some() && pushing && controller.can_climb(tr) {
+        controller.climb_start.get_or_insert(tr.translation.y);
+        ControllerState::Climbing
+    } else if out.grounded {
+        ControllerState::Grounded
+    } else {
+        ControllerState::Airborne
+    };
+}
+
+impl Controller {
+    /// Whether the climb since the last landing is still short of the limit.
+    fn can_climb(&self, tr: &Transform) -> bool {
+        self.climb_start
+            .map_or(true, |start| tr.translation.y - start < CLIMB_HEIGHT)
+    }
+
+    fn start_mantle(&mut self, top: Vec3, forward: Vec3) {
+        self.state = ControllerState::Mantling;
+        self.mantle = Some((top + forward * MANTLE_DEPTH + Vec3::Y * 0.05, 0.0));
+        self.jump_buffer = 0.0;
+    }
+}
+
+/// Integrates the player's velocity in m/s and moves the character controller by it.
+pub fn player_movement(
+    mut player_query: Query<(
+        &mut Player,
+        &mut Controller,
+        &Transform,
+        &mut KinematicCharacterController,
+        &KinematicCharacterControllerOutput,
+    )>,
+    anvil_held_query: Query<(), (With<Anvil>, With<Held>)>,
+    input: Res<FrameInput>,
+    progress: Res<Progress>,
+) {
+    let Ok((mut player, mut controller, tr, mut contr, out)) = player_query.get_single_mut() else {
+        return;
+    };
+    let dt = input.dt;
+    // the anvil slows down the climbing moves
+    let agility = if anvil_held_query.is_empty() {
+        1.0
+    } else {
+        CARRYING_CONTROL
+    };
+    match (controller.state, controller.mantle, controller.ledge) {
+        (ControllerState::Mantling, Some((target, secs)), _) => {
+            controller.mantle = Some((target, secs + dt));
+            let to_target = target - tr.translation;
+            let step = MANTLE_SPEED * agility * dt;
+            // up first, then over the edge
+            let translation = if to_target.y > 0.0 {
+                Vec3::Y * to_target.y.min(step)
+            } else {
+                let across = Vec3::new(to_target.x, 0.0, to_target.z);
+                across.clamp_length_max(step)
+            };
+            player.velocity = Vec3::ZERO;
+            contr.translation = Some(translation);
+            return;
+        }
+        (ControllerState::Hanging, _, Some(top)) => {
+            let wall_normal = controller.wall_normal.unwrap_or(tr.back());
+            let along = wall_normal.cross(Vec3::Y).normalize_or_zero();
+            let shimmy = along * movement_input(tr, &input).dot(along) * SHIMMY_SPEED * agility;
+            let hang = Vec3::Y * (top.y - HANG_HEIGHT - tr.translation.y);
+            player.velocity = Vec3::ZERO;
+            contr.translation = Some(hang + shimmy * dt);
+            return;
+        }
+        _ => {}
+    }
+    let mut acceleration = movement_input(tr, &input) * MOVE_ACCELERATION * player.speed;
+    if progress.powerups.contains(&Powerup::Speed) {
+        acceleration *= 1.2;
+    }
+    let friction = match controller.state {
+        ControllerState::Grounded
+        | ControllerState::Climbing
+        | ControllerState::Hanging
+        | ControllerState::Mantling => GROUND_FRICTION,
+        ControllerState::Airborne => AIR_FRICTION,
+        ControllerState::Carrying if controller.grounded => GROUND_FRICTION,
+        ControllerState::Carrying => CARRYING_AIR_FRICTION,
+        ControllerState::Launched => LAUNCHED_FRICTION,
+    };
+    if controller.state == ControllerState::Carrying {
+        acceleration *= CARRYING_CONTROL;
+    }
+    let old_velocity = player.velocity;
+    // exact over the frame, so the top speed doesn't depend on the frame rate
+    let decay = (-friction * dt).exp();
+    let gain = (1.0 - decay) / friction;
+    player.velocity.x = player.velocity.x * decay + acceleration.x * gain;
+    player.velocity.z = player.velocity.z * decay + acceleration.z * gain;
+
+    match controller.wall_normal {
+        Some(normal) if controller.state == ControllerState::Climbing => {
+            player.velocity.y = CLIMB_SPEED;
+            player.velocity -= normal * WALL_STICK_SPEED;
+        }
+        _ => {
+            if controller.grounded && player.velocity.y < 0.0 {
+                player.velocity.y = 0.0;
+            }
+            player.velocity.y -= GRAVITY * dt;
+        }
+    }
+
+    if input.just_pressed(Action::Jump) {
+        controller.jump_buffer = JUMP_BUFFER;
+    } else {
+        controller.jump_buffer -= dt;
+    }
+    // jumping off the anvil being held would be flying
+    let on_held_anvil = out
+        .collisions
+        .iter()
+        .any(|coll| anvil_held_query.contains(coll.entity));
+    if controller.jump_buffer > 0.0
+        && controller.airborne_secs < COYOTE_TIME
+        && controller.state != ControllerState::Launched
+        && !on_held_anvil
+    {
+        player.velocity.y = player.jump_strenght;
+        controller.jump_buffer = 0.0;
+        // no second jump from the same coyote time
+        controller.airborne_secs = COYOTE_TIME;
+    }
+
+    // the mean velocity of the frame, a jump reaches the same height at any frame rate
+    contr.translation = Some((old_velocity + player.velocity) * 0.5 * dt);
+}
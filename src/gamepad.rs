@@ -0,0 +1,111 @@
+use bevy::prelude::*;
+
+use crate::bindings::Action;
+
+/// Dead zones of the gamepad sticks and triggers, as a fraction of their range, from the
+/// [`Settings`](crate::settings::Settings).
+#[derive(Clone, Debug)]
+pub struct GamepadConfig {
+    pub move_dead_zone: f32,
+    pub look_dead_zone: f32,
+    pub trigger_threshold: f32,
+}
+
+/// The state of every connected gamepad, combined.
+#[derive(Default, Clone, Debug)]
+pub struct GamepadFrame {
+    /// Left stick, x to the right and y forward.
+    pub movement: Vec2,
+    /// Right stick, x to the right and y up.
+    pub look: Vec2,
+    pub pressed: Vec<Action>,
+}
+
+fn button_action(button: GamepadButtonType) -> Option<Action> {
+    match button {
+        GamepadButtonType::South => Some(Action::Jump),
+        GamepadButtonType::North => Some(Action::Help),
+        GamepadButtonType::West => Some(Action::Recall),
+        GamepadButtonType::Select => Some(Action::Reset),
+        _ => None,
+    }
+}
+
+/// Radial dead zone, rescaled so the output still starts from zero.
+fn apply_dead_zone(stick: Vec2, dead_zone: f32) -> Vec2 {
+    let length = stick.length();
+    if length <= dead_zone {
+        Vec2::ZERO
+    } else {
+        stick / length * ((length - dead_zone) / (1.0 - dead_zone)).min(1.0)
+    }
+}
+
+pub fn read_gamepads(
+    gamepads: &Gamepads,
+    axes: &Axis<GamepadAxis>,
+    buttons: &Input<GamepadButton>,
+    button_axes: &Axis<GamepadButton>,
+    config: &GamepadConfig,
+) -> GamepadFrame {
+    let mut frame = GamepadFrame::default();
+    for gamepad in gamepads.iter() {
+        let stick = |x, y| {
+            Vec2::new(
+                axes.get(GamepadAxis::new(gamepad, x)).unwrap_or_default(),
+                axes.get(GamepadAxis::new(gamepad, y)).unwrap_or_default(),
+            )
+        };
+        frame.movement += apply_dead_zone(
+            stick(GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY),
+            config.move_dead_zone,
+        );
+        frame.look += apply_dead_zone(
+            stick(GamepadAxisType::RightStickX, GamepadAxisType::RightStickY),
+            config.look_dead_zone,
+        );
+
+        let trigger = |button_type| {
+            button_axes
+                .get(GamepadButton::new(gamepad, button_type))
+                .unwrap_or_default()
+                > config.trigger_threshold
+        };
+        // left trigger grabs and attracts, right trigger throws
+        if trigger(GamepadButtonType::LeftTrigger2) {
+            frame.pressed.push(Action::Grab);
+        }
+        if trigger(GamepadButtonType::RightTrigger2) {
+            frame.pressed.push(Action::Throw);
+        }
+        for button in buttons.get_pressed() {
+            if button.gamepad == gamepad {
+                frame.pressed.extend(button_action(button.button_type));
+            }
+        }
+    }
+    frame.movement = frame.movement.clamp_length_max(1.0);
+    frame.look = frame.look.clamp_length_max(1.0);
+    frame
+}
+
+fn just_pressed(
+    gamepads: &Gamepads,
+    buttons: &Input<GamepadButton>,
+    button_type: GamepadButtonType,
+) -> bool {
+    gamepads
+        .iter()
+        .any(|gamepad| buttons.just_pressed(GamepadButton::new(gamepad, button_type)))
+}
+
+/// Whether start was pressed on any gamepad, to pause.
+pub fn start_pressed(gamepads: &Gamepads, buttons: &Input<GamepadButton>) -> bool {
+    just_pressed(gamepads, buttons, GamepadButtonType::Start)
+}
+
+/// Whether south or start was pressed on any gamepad, to get past a screen.
+pub fn continue_pressed(gamepads: &Gamepads, buttons: &Input<GamepadButton>) -> bool {
+    just_pressed(gamepads, buttons, GamepadButtonType::South)
+        || just_pressed(gamepads, buttons, GamepadButtonType::Start)
+}
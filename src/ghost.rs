@@ -0,0 +1,220 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    level::CurrentLevel,
+    save::{read_ron, write_ron, SaveData},
+    timer::RunTimer,
+    Anvil, GameAssets, Player, Transient,
+};
+
+/// Seconds between two recorded samples.
+const GHOST_INTERVAL: f32 = 0.05;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GhostSample {
+    pub time: f32,
+    pub player: Transform,
+    pub anvil: Transform,
+}
+
+/// Trajectory of the personal best run of the current level.
+#[derive(Resource, Default, Clone, Debug)]
+pub struct Ghost {
+    pub level: Option<String>,
+    pub samples: Vec<GhostSample>,
+}
+
+/// Trajectory of the run in progress, becomes the [`Ghost`] if it's a new best.
+#[derive(Resource, Default, Clone, Debug)]
+pub struct GhostRecorder {
+    pub samples: Vec<GhostSample>,
+}
+
+#[derive(Resource)]
+pub struct GhostAssets {
+    capsule: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+#[derive(Component)]
+pub struct GhostCourier;
+
+#[derive(Component)]
+pub struct GhostAnvil;
+
+/// Marks meshes of the ghost anvil scene that already use the ghost material.
+#[derive(Component)]
+pub struct GhostMaterial;
+
+fn ghost_key(level: &str) -> String {
+    format!("ghost.{level}")
+}
+
+pub fn setup_ghost_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(GhostAssets {
+        // same as the player collider
+        capsule: meshes.add(Mesh::from(shape::Capsule {
+            radius: 0.25,
+            depth: 1.25,
+            ..default()
+        })),
+        material: materials.add(StandardMaterial {
+            base_color: Color::rgba(0.6, 0.8, 1.0, 0.3),
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            ..default()
+        }),
+    });
+}
+
+pub fn load_ghost(mut ghost: ResMut<Ghost>, current_level: Res<CurrentLevel>) {
+    let level = &current_level.info.name;
+    if ghost.level.as_ref() == Some(level) {
+        return;
+    }
+    let samples = read_ron(&ghost_key(level), &format!("The ghost of {}", level));
+    *ghost = Ghost {
+        level: Some(level.clone()),
+        samples,
+    };
+}
+
+/// Keeps the trajectory of a new personal best as the ghost.
+pub fn save_ghost(
+    mut ghost: ResMut<Ghost>,
+    recorder: Res<GhostRecorder>,
+    save: Res<SaveData>,
+    run_timer: Res<RunTimer>,
+    current_level: Res<CurrentLevel>,
+) {
+    let level = &current_level.info.name;
+    if save.best_times.get(level) != Some(&run_timer.elapsed) {
+        return;
+    }
+    ghost.samples = recorder.samples.clone();
+    // thousands of samples, kept compact
+    write_ron(
+        &ghost_key(level),
+        &ghost.samples,
+        &format!("the ghost of {}", level),
+        false,
+    );
+}
+
+pub fn spawn_ghost(
+    mut commands: Commands,
+    ghost: Res<Ghost>,
+    ghost_assets: Res<GhostAssets>,
+    game_assets: Res<GameAssets>,
+) {
+    let Some(first) = ghost.samples.first() else {
+        return;
+    };
+    // no colliders: the ghosts don't interact with the physics
+    commands
+        .spawn((
+            Name::new("GhostCourier"),
+            GhostCourier,
+            SpatialBundle::from_transform(first.player),
+            Transient::default(),
+        ))
+        .with_children(|parent| {
+            parent.spawn(PbrBundle {
+                mesh: ghost_assets.capsule.clone(),
+                material: ghost_assets.material.clone(),
+                transform: Transform::from_xyz(0.0, 0.875, 0.0),
+                ..default()
+            });
+        });
+    commands.spawn((
+        Name::new("GhostAnvil"),
+        GhostAnvil,
+        SceneBundle {
+            scene: game_assets.anvil.clone(),
+            transform: first.anvil,
+            ..default()
+        },
+        Transient::default(),
+    ));
+}
+
+pub fn ghost_materials(
+    mut commands: Commands,
+    ghost_anvil_query: Query<Entity, With<GhostAnvil>>,
+    children: Query<&Children>,
+    material_query: Query<(), (With<Handle<StandardMaterial>>, Without<GhostMaterial>)>,
+    ghost_assets: Res<GhostAssets>,
+) {
+    for ghost_anvil in ghost_anvil_query.iter() {
+        for descendant in children.iter_descendants(ghost_anvil) {
+            if material_query.get(descendant).is_ok() {
+                commands
+                    .entity(descendant)
+                    .insert((ghost_assets.material.clone(), GhostMaterial));
+            }
+        }
+    }
+}
+
+pub fn record_ghost(
+    mut recorder: ResMut<GhostRecorder>,
+    run_timer: Res<RunTimer>,
+    player_query: Query<&Transform, (With<Player>, Without<Anvil>)>,
+    anvil_query: Query<&Transform, (With<Anvil>, Without<Player>)>,
+) {
+    if let Some(last) = recorder.samples.last() {
+        if last.time > run_timer.elapsed {
+            // the timer was restarted, so is the run
+            recorder.samples.clear();
+        } else if run_timer.elapsed - last.time < GHOST_INTERVAL {
+            return;
+        }
+    }
+    if let (Ok(player), Ok(anvil)) = (player_query.get_single(), anvil_query.get_single()) {
+        recorder.samples.push(GhostSample {
+            time: run_timer.elapsed,
+            player: *player,
+            anvil: *anvil,
+        });
+    }
+}
+
+fn lerp_transform(a: &Transform, b: &Transform, t: f32) -> Transform {
+    Transform {
+        translation: a.translation.lerp(b.translation, t),
+        rotation: a.rotation.slerp(b.rotation, t),
+        scale: a.scale.lerp(b.scale, t),
+    }
+}
+
+pub fn move_ghost(
+    ghost: Res<Ghost>,
+    run_timer: Res<RunTimer>,
+    mut courier_query: Query<&mut Transform, (With<GhostCourier>, Without<GhostAnvil>)>,
+    mut anvil_query: Query<&mut Transform, (With<GhostAnvil>, Without<GhostCourier>)>,
+) {
+    let samples = &ghost.samples;
+    if samples.is_empty() {
+        return;
+    }
+    let next = samples
+        .partition_point(|sample| sample.time <= run_timer.elapsed)
+        .min(samples.len() - 1);
+    let (a, b) = (&samples[next.saturating_sub(1)], &samples[next]);
+    let t = if b.time > a.time {
+        ((run_timer.elapsed - a.time) / (b.time - a.time)).clamp(0.0, 1.0)
+    } else {
+        1.0
+    };
+    if let Ok(mut tr) = courier_query.get_single_mut() {
+        *tr = lerp_transform(&a.player, &b.player, t);
+    }
+    if let Ok(mut tr) = anvil_query.get_single_mut() {
+        *tr = lerp_transform(&a.anvil, &b.anvil, t);
+    }
+}
@@ -0,0 +1,186 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::{
+    bindings::Action,
+    level::{CurrentLevel, Level},
+    replay::FrameInput,
+    timer::RunTimer,
+    Anvil, GameState, Held, Player,
+};
+
+/// Speed in m/s under which the anvil counts as resting.
+const RESTING_SPEED: f32 = 0.5;
+/// Height above the player where a held anvil is put back, the height it's held at.
+const HELD_SAFE_HEIGHT: f32 = 0.85;
+
+/// A tagged scene mesh sending back whoever enters it, e.g. water or lava.
+#[derive(Component)]
+pub struct KillVolume;
+
+/// Last place the anvil rested at, where it's returned after a fall. The rotation it rested
+/// in too, in any other it could be put back into the ground.
+#[derive(Component, Default)]
+pub struct SafePosition {
+    last: Transform,
+    /// Where the anvil spawned, for when the last place is under the kill height anyway.
+    spawn: Transform,
+}
+
+impl SafePosition {
+    fn get(&self, level: &Level) -> Transform {
+        if self.last.translation.y < level.kill_height {
+            self.spawn
+        } else {
+            self.last
+        }
+    }
+}
+
+/// Starts from wherever the anvil was spawned, checkpoint included.
+pub fn reset_safe_positions(mut anvil_query: Query<(&Transform, &mut SafePosition)>) {
+    for (tr, mut safe) in anvil_query.iter_mut() {
+        *safe = SafePosition {
+            last: *tr,
+            spawn: *tr,
+        };
+    }
+}
+
+/// Whether `ent` at `tr` is under the kill height of the level or inside a kill volume.
+fn fell(
+    ent: Entity,
+    tr: &Transform,
+    level: &Level,
+    kill_query: &Query<Entity, With<KillVolume>>,
+    rapier_context: &RapierContext,
+) -> bool {
+    tr.translation.y < level.kill_height
+        || kill_query
+            .iter()
+            .any(|volume| rapier_context.intersection_pair(volume, ent) == Some(true))
+}
+
+/// Whether the collider of `ent` touches a fixed one, so the level rather than a prop.
+fn touches_fixed(rapier_context: &RapierContext, ent: Entity) -> bool {
+    let is_fixed = |handle| {
+        rapier_context
+            .colliders
+            .get(handle)
+            .is_some_and(|collider| {
+                collider.parent().map_or(true, |body| {
+                    rapier_context
+                        .bodies
+                        .get(body)
+                        .is_some_and(|body| body.is_fixed())
+                })
+            })
+    };
+    rapier_context
+        .contacts_with(ent)
+        .filter(|pair| pair.has_any_active_contacts())
+        .any(|pair| is_fixed(pair.raw.collider1) || is_fixed(pair.raw.collider2))
+}
+
+pub fn update_safe_positions(
+    mut anvil_query: Query<
+        (
+            Entity,
+            &Transform,
+            &Velocity,
+            &mut SafePosition,
+            Option<&Held>,
+        ),
+        With<Anvil>,
+    >,
+    player_query: Query<
+        (Entity, &Transform, &KinematicCharacterControllerOutput),
+        (With<Player>, Without<Anvil>),
+    >,
+    kill_query: Query<Entity, With<KillVolume>>,
+    rapier_context: Res<RapierContext>,
+    current_level: Res<CurrentLevel>,
+) {
+    let level = &current_level.info;
+    let player = player_query.get_single().ok();
+    for (ent, tr, vel, mut safe, held) in anvil_query.iter_mut() {
+        // grounded or resting on a kill volume is no safer than falling into it
+        if fell(ent, tr, level, &kill_query, &rapier_context) {
+            continue;
+        }
+        match held {
+            // held out in front it can be over the edge, the player stands on the ground though
+            Some(_) => {
+                let Some((ent_player, tr_player, _)) = player.filter(|(_, _, out)| out.grounded)
+                else {
+                    continue;
+                };
+                if !fell(ent_player, tr_player, level, &kill_query, &rapier_context) {
+                    safe.last = Transform {
+                        translation: tr_player.translation + Vec3::Y * HELD_SAFE_HEIGHT,
+                        ..*tr
+                    };
+                }
+            }
+            // slow at the top of a throw too, so it has to lie on the level
+            None => {
+                if vel.linvel.length_squared() < RESTING_SPEED * RESTING_SPEED
+                    && touches_fixed(&rapier_context, ent)
+                {
+                    safe.last = *tr;
+                }
+            }
+        }
+    }
+}
+
+/// Brings a lost anvil back where it last rested or was held, leaving the rest of the level as is.
+pub fn recall_anvil(
+    mut anvil_query: Query<
+        (&mut Transform, &mut Velocity, &SafePosition),
+        (With<Anvil>, Without<Held>),
+    >,
+    input: Res<FrameInput>,
+    current_level: Res<CurrentLevel>,
+) {
+    if !input.just_pressed(Action::Recall) {
+        return;
+    }
+    for (mut tr, mut vel, safe) in anvil_query.iter_mut() {
+        *tr = safe.get(&current_level.info);
+        *vel = Velocity::zero();
+    }
+}
+
+/// Resets the level when the player falls below the kill height or into a kill volume, the
+/// anvil is only put back where it last rested. Both cost the penalty of the level.
+pub fn check_falls(
+    player_query: Query<(Entity, &Transform), With<Player>>,
+    mut anvil_query: Query<
+        (Entity, &mut Transform, &mut Velocity, &SafePosition),
+        (With<Anvil>, Without<Held>, Without<Player>),
+    >,
+    kill_query: Query<Entity, With<KillVolume>>,
+    rapier_context: Res<RapierContext>,
+    current_level: Res<CurrentLevel>,
+    mut run_timer: ResMut<RunTimer>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let level = &current_level.info;
+    if let Ok((ent, tr)) = player_query.get_single() {
+        if fell(ent, tr, level, &kill_query, &rapier_context) {
+            info!("The player fell, {}s penalty", level.fall_penalty);
+            run_timer.elapsed += level.fall_penalty;
+            next_state.set(GameState::PrepareScene);
+            return;
+        }
+    }
+    for (ent, mut tr, mut vel, safe) in anvil_query.iter_mut() {
+        if fell(ent, &tr, level, &kill_query, &rapier_context) {
+            info!("The anvil fell, {}s penalty", level.fall_penalty);
+            run_timer.elapsed += level.fall_penalty;
+            *tr = safe.get(level);
+            *vel = Velocity::zero();
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+
+use crate::{
+    bindings::{Action, Bindings},
+    gamepad::continue_pressed,
+    loading::LoadingError,
+    save::SaveData,
+    timer::{format_time, RunTimer},
+    GameAssets, GameState, Powerup, Progress, Transient,
+};
+
+/// The campaign, as described by `assets/campaign.levels.ron`.
+#[derive(Deserialize, TypeUuid, Clone, Debug)]
+#[uuid = "6a3b3f3e-9f5d-4c1e-8a2f-2d3c4b5a6e7f"]
+pub struct LevelManifest {
+    pub levels: Vec<Level>,
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
+pub struct Level {
+    pub name: String,
+    /// Asset path of the glTF scene, e.g. `testcity.gltf#Scene0`.
+    pub scene: String,
+    pub player_spawn: Vec3,
+    pub anvil_spawn: Vec3,
+    /// Number of customers to deliver to.
+    pub objectives: u32,
+    /// Target completion time in seconds.
+    pub par_time: f32,
+    /// Powerup unlocked by each objective, indexed by objective number.
+    pub powerups: Vec<Powerup>,
+    /// Height under which the player and the anvil have fallen out of the level.
+    #[serde(default = "default_kill_height")]
+    pub kill_height: f32,
+    /// Seconds added to the run time by each fall.
+    #[serde(default = "default_fall_penalty")]
+    pub fall_penalty: f32,
+}
+
+fn default_kill_height() -> f32 {
+    -50.0
+}
+
+fn default_fall_penalty() -> f32 {
+    5.0
+}
+
+#[derive(Default)]
+pub struct LevelManifestLoader;
+
+impl AssetLoader for LevelManifestLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let manifest: LevelManifest = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(manifest));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["levels.ron"]
+    }
+}
+
+/// The level being played, copied out of the manifest.
+#[derive(Resource, Default, Clone, Debug)]
+pub struct CurrentLevel {
+    pub index: usize,
+    pub info: Level,
+    pub scene: Handle<Scene>,
+}
+
+/// Strong handles to every level scene, so switching level doesn't reload them.
+#[derive(Resource, Default, Debug)]
+pub struct LevelScenes {
+    pub scenes: Vec<Handle<Scene>>,
+}
+
+pub fn load_level_scenes(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    manifests: Res<Assets<LevelManifest>>,
+    asset_server: Res<AssetServer>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let manifest = manifests.get(&game_assets.levels);
+    let scenes = LevelScenes {
+        scenes: manifest
+            .iter()
+            .flat_map(|manifest| manifest.levels.iter())
+            .map(|level| asset_server.load(level.scene.as_str()))
+            .collect(),
+    };
+    match manifest.and_then(|manifest| select_level(manifest, 0, &scenes)) {
+        Some(current_level) => commands.insert_resource(current_level),
+        None => {
+            let message = "The level manifest has no levels".to_string();
+            error!("{}", message);
+            commands.insert_resource(LoadingError(message));
+            next_state.set(GameState::LoadingFailed);
+        }
+    }
+    // even empty, the menu buttons wait for it
+    commands.insert_resource(scenes);
+}
+
+/// Builds the [`CurrentLevel`] for the level at `index` in the manifest, if there's one.
+pub fn select_level(
+    manifest: &LevelManifest,
+    index: usize,
+    scenes: &LevelScenes,
+) -> Option<CurrentLevel> {
+    Some(CurrentLevel {
+        index,
+        info: manifest.levels.get(index)?.clone(),
+        scene: scenes.scenes.get(index)?.clone(),
+    })
+}
+
+/// Stats kept across the levels of a campaign, while [`Progress`] is per level.
+#[derive(Resource, Default, Clone, Debug)]
+pub struct Campaign {
+    pub completed: Vec<String>,
+    pub powerups: Vec<Powerup>,
+    pub deliveries: u32,
+    pub broken: u32,
+    pub time: f32,
+}
+
+pub fn finish_level(
+    mut campaign: ResMut<Campaign>,
+    progress: Res<Progress>,
+    current_level: Res<CurrentLevel>,
+    run_timer: Res<RunTimer>,
+) {
+    campaign.completed.push(current_level.info.name.clone());
+    campaign.deliveries += progress.objectives.len() as u32;
+    campaign.broken += progress.broken.len() as u32;
+    campaign.time += run_timer.elapsed;
+    for powerup in progress.powerups.iter() {
+        if !campaign.powerups.contains(powerup) {
+            campaign.powerups.push(powerup.clone());
+        }
+    }
+}
+
+pub fn level_complete_ui(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    manifests: Res<Assets<LevelManifest>>,
+    campaign: Res<Campaign>,
+    progress: Res<Progress>,
+    current_level: Res<CurrentLevel>,
+    save: Res<SaveData>,
+    run_timer: Res<RunTimer>,
+    bindings: Res<Bindings>,
+) {
+    let level = &current_level.info;
+    let time = run_timer.elapsed;
+    let best = match save.best_times.get(&level.name) {
+        Some(best) if *best >= time => "New best time!".to_string(),
+        Some(best) => format!("Best time: {}", format_time(*best)),
+        None => String::new(),
+    };
+    let last = match manifests.get(&game_assets.levels) {
+        Some(manifest) => current_level.index + 1 >= manifest.levels.len(),
+        None => true,
+    };
+    let par = if time <= level.par_time {
+        "under par!"
+    } else {
+        "over par."
+    };
+    let mut text = format!(
+        "Level {} complete: {}\n\n\
+        Delivered {} anvils in {}, {}\n\
+        Par time: {}\n\
+        {}\n\
+        Broken: {}\n\n\
+        Campaign: {} levels, {} anvils, {} broken, {}\n\n",
+        current_level.index + 1,
+        level.name,
+        progress.objectives.len(),
+        format_time(time),
+        par,
+        format_time(level.par_time),
+        best,
+        progress.broken.len(),
+        campaign.completed.len(),
+        campaign.deliveries,
+        campaign.broken,
+        format_time(campaign.time),
+    );
+    let jump = bindings.key_name(Action::Jump);
+    if last {
+        text += &format!(
+            "You have completed the campaign!\n[{}] or (A) to return to the main menu.",
+            jump
+        );
+    } else {
+        text += &format!("[{}] or (A) to continue to the next level.", jump);
+    }
+
+    commands.spawn((Camera2dBundle::default(), Transient::default()));
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect::new(
+                        Val::Percent(10.0),
+                        Val::Percent(0.0),
+                        Val::Percent(20.0),
+                        Val::Percent(0.0),
+                    ),
+                    size: Size::new(Val::Percent(80.0), Val::Percent(60.0)),
+                    ..default()
+                },
+                ..default()
+            },
+            Transient::default(),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle {
+                    text: Text::from_section(
+                        text,
+                        TextStyle {
+                            font: game_assets.font.clone(),
+                            font_size: 30.0,
+                            color: Color::WHITE,
+                        },
+                    ),
+                    style: Style {
+                        size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                        ..default()
+                    },
+                    ..default()
+                },
+                Transient::default(),
+            ));
+        });
+}
+
+/// Moves on to the next level of the campaign, or back to the main menu after the last one.
+pub fn advance_level(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    bindings: Res<Bindings>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    game_assets: Res<GameAssets>,
+    manifests: Res<Assets<LevelManifest>>,
+    scenes: Res<LevelScenes>,
+    current_level: Res<CurrentLevel>,
+    mut campaign: ResMut<Campaign>,
+    mut progress: ResMut<Progress>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if !keys.just_pressed(bindings.key(Action::Jump))
+        && !continue_pressed(&gamepads, &gamepad_buttons)
+    {
+        return;
+    }
+    let Some(manifest) = manifests.get(&game_assets.levels) else {
+        return;
+    };
+    let Some(next_level) = select_level(manifest, current_level.index + 1, &scenes) else {
+        *campaign = Campaign::default();
+        next_state.set(GameState::MainMenu);
+        return;
+    };
+    commands.insert_resource(next_level);
+    *progress = Progress {
+        objectives: vec![],
+        powerups: campaign.powerups.clone(),
+        help: progress.help,
+        broken: vec![],
+        checkpoint: None,
+    };
+    next_state.set(GameState::PrepareScene);
+}
@@ -0,0 +1,275 @@
+use bevy::{asset::LoadState, prelude::*, scene::SceneInstance};
+use bevy_asset_loader::prelude::*;
+
+use crate::GameState;
+
+/// Seconds to wait for the scenes of the level before giving up on it.
+const SCENE_TIMEOUT: f32 = 30.0;
+/// Seconds before the loading screen shows up in [`GameState::PrepareScene`], resets are faster.
+const LOADING_SCREEN_DELAY: f32 = 0.2;
+
+/// Progress of the scenes spawned for the level, tracked while in [`GameState::PrepareScene`].
+#[derive(Resource, Default, Debug)]
+pub struct SceneLoading {
+    pub elapsed: f32,
+    /// Scenes and meshes ready, out of those known so far.
+    pub ready: usize,
+    pub total: usize,
+    /// Everything was ready on the last update.
+    all_ready: bool,
+    pub done: bool,
+}
+
+/// Handles of an asset collection being loaded, which the loader keeps to itself.
+#[derive(Resource, Default, Debug)]
+pub struct CollectionHandles(Vec<HandleUntyped>);
+
+/// Asks the collection for its handles, the asset server hands out the ones the loader got.
+pub fn collect_handles<A: AssetCollection>(world: &mut World) {
+    let handles = A::load(world);
+    world.insert_resource(CollectionHandles(handles));
+}
+
+pub fn drop_collection_handles(mut commands: Commands) {
+    commands.remove_resource::<CollectionHandles>();
+}
+
+/// Why the level could not be prepared, shown in [`GameState::LoadingFailed`].
+#[derive(Resource, Default, Debug)]
+pub struct LoadingError(pub String);
+
+pub fn start_scene_loading(mut loading: ResMut<SceneLoading>) {
+    *loading = default();
+}
+
+/// Run condition of the systems needing every scene spawned and its meshes loaded.
+pub fn scenes_ready(loading: Res<SceneLoading>) -> bool {
+    loading.done
+}
+
+/// Checks each spawned scene and its meshes, failing the level if an asset doesn't load.
+pub fn track_scene_loading(
+    mut commands: Commands,
+    scene_query: Query<(Entity, &Handle<Scene>, Option<&SceneInstance>)>,
+    children: Query<&Children>,
+    mesh_query: Query<&Handle<Mesh>>,
+    scene_spawner: Res<SceneSpawner>,
+    asset_server: Res<AssetServer>,
+    meshes: Res<Assets<Mesh>>,
+    time: Res<Time>,
+    mut loading: ResMut<SceneLoading>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if loading.done {
+        return;
+    }
+    let path = |handle: HandleUntyped| {
+        asset_server
+            .get_handle_path(handle)
+            .map(|path| path.path().display().to_string())
+            .unwrap_or_else(|| "<unknown>".to_string())
+    };
+    let mut failure = None;
+    let (mut ready, mut total) = (0, 0);
+    for (ent, scene, instance) in scene_query.iter() {
+        total += 1;
+        match instance {
+            Some(instance) if scene_spawner.instance_is_ready(**instance) => ready += 1,
+            _ => {
+                if asset_server.get_load_state(scene) == LoadState::Failed {
+                    failure = Some(format!(
+                        "Could not load the scene {}",
+                        path(scene.clone_untyped())
+                    ));
+                }
+                continue;
+            }
+        }
+        for descendant in children.iter_descendants(ent) {
+            let Ok(mesh) = mesh_query.get(descendant) else {
+                continue;
+            };
+            total += 1;
+            if meshes.contains(mesh) {
+                ready += 1;
+            } else if asset_server.get_load_state(mesh) == LoadState::Failed {
+                failure = Some(format!(
+                    "Could not load the mesh {}",
+                    path(mesh.clone_untyped())
+                ));
+            }
+        }
+    }
+    loading.elapsed += time.delta_seconds();
+    loading.ready = ready;
+    loading.total = total;
+    let all_ready = total > 0 && ready == total;
+    // one more frame, so the loading screen says colliders are next before the hitch
+    loading.done = all_ready && loading.all_ready;
+    loading.all_ready = all_ready;
+    if failure.is_none() && !loading.done && loading.elapsed > SCENE_TIMEOUT {
+        failure = Some(format!(
+            "Timed out after {:.0}s with {}/{} scenes and meshes ready",
+            SCENE_TIMEOUT, ready, total
+        ));
+    }
+    if let Some(message) = failure {
+        error!("Could not prepare the level: {}", message);
+        commands.insert_resource(LoadingError(message));
+        next_state.set(GameState::LoadingFailed);
+    }
+}
+
+/// Loaded on their own, the loading screen shows before the `GameAssets` collection is there.
+#[derive(Resource)]
+pub struct LoadingScreenAssets {
+    font: Handle<Font>,
+    icon: Handle<Image>,
+}
+
+#[derive(Component)]
+pub struct LoadingScreen;
+
+#[derive(Component)]
+pub struct LoadingText;
+
+#[derive(Component)]
+pub struct LoadingBar;
+
+fn spawn_loading_screen(commands: &mut Commands, assets: &LoadingScreenAssets) -> Entity {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    gap: Size::all(Val::Px(20.0)),
+                    ..default()
+                },
+                background_color: Color::BLACK.into(),
+                // above the HUD spawned along with the level
+                z_index: ZIndex::Global(1),
+                ..default()
+            },
+            LoadingScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn(ImageBundle {
+                style: Style {
+                    size: Size::new(Val::Px(200.0), Val::Px(200.0)),
+                    ..default()
+                },
+                image: assets.icon.clone().into(),
+                ..default()
+            });
+            parent.spawn((
+                TextBundle::from_section(
+                    "Loading",
+                    TextStyle {
+                        font: assets.font.clone(),
+                        font_size: 30.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                LoadingText,
+            ));
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(400.0), Val::Px(12.0)),
+                        ..default()
+                    },
+                    background_color: Color::rgb(0.15, 0.15, 0.15).into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn((
+                        NodeBundle {
+                            style: Style {
+                                size: Size::new(Val::Percent(0.0), Val::Percent(100.0)),
+                                ..default()
+                            },
+                            background_color: Color::rgb(1.0, 0.5, 0.1).into(),
+                            ..default()
+                        },
+                        LoadingBar,
+                    ));
+                });
+        })
+        .id()
+}
+
+pub fn setup_loading_screen(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let assets = LoadingScreenAssets {
+        font: asset_server.load("monof55.ttf"),
+        icon: asset_server.load("anvil-icon.png"),
+    };
+    commands.spawn((Camera2dBundle::default(), LoadingScreen));
+    spawn_loading_screen(&mut commands, &assets);
+    commands.insert_resource(assets);
+}
+
+/// The level has the player camera to show the screen, which stays hidden for quick resets.
+pub fn level_loading_screen(mut commands: Commands, assets: Res<LoadingScreenAssets>) {
+    let screen = spawn_loading_screen(&mut commands, &assets);
+    commands.entity(screen).insert(Visibility::Hidden);
+}
+
+pub fn despawn_loading_screen(
+    mut commands: Commands,
+    screen_query: Query<Entity, With<LoadingScreen>>,
+) {
+    for ent in screen_query.iter() {
+        commands.entity(ent).despawn_recursive();
+    }
+}
+
+pub fn update_loading_screen(
+    state: Res<State<GameState>>,
+    asset_server: Res<AssetServer>,
+    collection: Option<Res<CollectionHandles>>,
+    scene_loading: Res<SceneLoading>,
+    mut screen_query: Query<&mut Visibility, (With<LoadingScreen>, With<Node>)>,
+    mut text_query: Query<&mut Text, With<LoadingText>>,
+    mut bar_query: Query<&mut Style, With<LoadingBar>>,
+) {
+    let (label, ready, total) = match state.0 {
+        GameState::AssetLoading => {
+            let handles = collection
+                .as_ref()
+                .map_or(&[][..], |collection| &collection.0);
+            let ready = handles
+                .iter()
+                .filter(|handle| asset_server.get_load_state(*handle) == LoadState::Loaded)
+                .count();
+            ("Loading assets", ready, handles.len())
+        }
+        GameState::PrepareScene => {
+            if scene_loading.elapsed > LOADING_SCREEN_DELAY {
+                for mut visibility in screen_query.iter_mut() {
+                    *visibility = Visibility::Visible;
+                }
+            }
+            let label = if scene_loading.all_ready {
+                "Building colliders"
+            } else {
+                "Loading the level"
+            };
+            (label, scene_loading.ready, scene_loading.total)
+        }
+        _ => return,
+    };
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = format!("{} {}/{}", label, ready, total);
+    }
+    let fraction = if total == 0 {
+        0.0
+    } else {
+        ready as f32 / total as f32
+    };
+    for mut style in bar_query.iter_mut() {
+        style.size.width = Val::Percent(fraction * 100.0);
+    }
+}
@@ -1,19 +1,91 @@
-use std::f32::consts::PI;
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
+
+use std::{f32::consts::PI, time::Duration};
 
 use bevy::{
     core_pipeline::bloom::BloomSettings,
-    input::mouse::MouseMotion,
-    pbr::{CascadeShadowConfigBuilder, DirectionalLightShadowMap},
+    gltf::{Gltf, GltfExtras},
+    input::InputSystem,
+    pbr::CascadeShadowConfigBuilder,
     prelude::*,
+    render::primitives::Aabb,
     scene::SceneInstance,
     window::CursorGrabMode,
 };
 use bevy_asset_loader::prelude::*;
 use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+mod audio;
+mod bindings;
+mod boost;
+mod breakable;
+mod colliders;
+mod controller;
+mod gamepad;
+mod ghost;
+mod hazards;
+mod level;
+mod loading;
+mod menu;
+mod replay;
+mod save;
+mod settings;
+mod surface;
+mod tags;
+mod timer;
+
+use audio::{
+    apply_audio_settings, duck_level_complete, hit_events, soundtrack, update_mixer, AudioMixer,
+};
+use bindings::{help_text, load_bindings, rebind_keys, Action, Bindings, Rebinding};
+use boost::{anvil_boost, update_launch_text, LaunchText, CATAPULT_IMPULSE, CATAPULT_SPEED};
+use breakable::{
+    break_scenery, despawn_fragments, score_text, update_score_text, Breakable, ScoreText,
+};
+use colliders::{ColliderCache, ColliderShape};
+use controller::{player_look, player_movement, update_controller_state, Controller};
+use ghost::{
+    ghost_materials, load_ghost, move_ghost, record_ghost, save_ghost, setup_ghost_assets,
+    spawn_ghost, Ghost, GhostAssets, GhostRecorder,
+};
+use hazards::{
+    check_falls, recall_anvil, reset_safe_positions, update_safe_positions, KillVolume,
+    SafePosition,
+};
+use level::{
+    advance_level, finish_level, level_complete_ui, load_level_scenes, Campaign, CurrentLevel,
+    Level, LevelManifest, LevelManifestLoader, LevelScenes,
+};
+use loading::{
+    collect_handles, despawn_loading_screen, drop_collection_handles, level_loading_screen,
+    scenes_ready, setup_loading_screen, start_scene_loading, track_scene_loading,
+    update_loading_screen, LoadingError, SceneLoading,
+};
+use menu::{
+    loading_failed_ui, main_menu_ui, menu_buttons, pause_game, release_cursor, resume_game,
+    submenu_buttons, toggle_pause, unpause, update_submenu_labels, PauseState,
+};
+use replay::{
+    finish_replay, gather_live_input, load_replay, read_frame_input, start_replay, FrameInput,
+    GameplaySet, LiveInput, PhysicsPlugin, Replay,
+};
+use save::{load_save, save_progress};
+use settings::{apply_settings, load_settings, Settings};
+use surface::{scene_surface, setup_surface_assets, update_particles, Surface};
+use tags::{SceneTags, SpawnPoint};
+use timer::{
+    start_run_timer, stop_run_timer, tick_run_timer, update_run_timer_text, RunTimer, RunTimerText,
+};
 
 fn main() {
+    let replay = Replay::from_args();
+    // replays need the gameplay and the physics to step the same way on every run
+    let fixed_timestep = replay.fixed_timestep();
+    let gameplay_schedule = replay.gameplay_schedule();
     App::new()
         .add_state::<GameState>()
+        .add_state::<PauseState>()
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             primary_window: Some(Window {
                 fit_canvas_to_parent: true,
@@ -21,46 +93,176 @@ fn main() {
             }),
             ..default()
         }))
-        .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
+        .add_plugin(PhysicsPlugin { fixed_timestep })
         //.add_plugin(RapierDebugRenderPlugin::default())
-        .insert_resource(RapierConfiguration {
-            timestep_mode: TimestepMode::Variable {
-                max_dt: 1.0 / 20.0,
-                time_scale: 1.0,
-                substeps: 8,
-            },
-            ..default()
-        })
+        .insert_resource(replay)
+        .init_resource::<FrameInput>()
+        .init_resource::<LiveInput>()
+        .add_startup_system(load_replay)
         //.add_plugin(WorldInspectorPlugin::new())
         .insert_resource(ClearColor(Color::BLACK))
+        .add_asset::<LevelManifest>()
+        .init_asset_loader::<LevelManifestLoader>()
         .add_loading_state(
-            LoadingState::new(GameState::AssetLoading).continue_to_state(GameState::PrepareScene),
+            LoadingState::new(GameState::AssetLoading).continue_to_state(GameState::MainMenu),
         )
         .add_collection_to_loading_state::<_, GameAssets>(GameState::AssetLoading)
         .add_systems(
-            (grab_cursor, setup_graphics, spawn_player, player_ui)
+            (
+                grab_cursor,
+                setup_graphics,
+                spawn_player,
+                player_ui,
+                load_ghost.before(spawn_ghost),
+                spawn_ghost,
+                reset_level_scene,
+                start_scene_loading,
+                level_loading_screen,
+            )
                 .in_schedule(OnEnter(GameState::PrepareScene)),
         )
-        .add_system(add_scene_colliders.in_set(OnUpdate(GameState::PrepareScene)))
-        .add_system(soundtrack.in_schedule(OnExit(GameState::AssetLoading)))
+        .add_system(ghost_materials.run_if(resource_exists::<GhostAssets>()))
+        .add_systems(
+            (
+                track_scene_loading,
+                add_scene_colliders.run_if(scenes_ready),
+            )
+                .chain()
+                .in_set(OnUpdate(GameState::PrepareScene)),
+        )
+        .add_systems(
+            (setup_loading_screen, collect_handles::<GameAssets>)
+                .in_schedule(OnEnter(GameState::AssetLoading)),
+        )
+        .add_systems(
+            (despawn_loading_screen, drop_collection_handles)
+                .in_schedule(OnExit(GameState::AssetLoading)),
+        )
+        .add_system(despawn_loading_screen.in_schedule(OnExit(GameState::PrepareScene)))
+        .add_system(update_loading_screen.after(track_scene_loading))
+        .add_systems(
+            (reset, unload_level_scene, release_cursor, loading_failed_ui)
+                .chain()
+                .in_schedule(OnEnter(GameState::LoadingFailed)),
+        )
+        .add_system(reset.in_schedule(OnExit(GameState::LoadingFailed)))
+        .add_systems(
+            (
+                soundtrack,
+                load_level_scenes,
+                setup_ghost_assets,
+                setup_surface_assets,
+            )
+                .in_schedule(OnExit(GameState::AssetLoading)),
+        )
+        .add_systems(
+            (release_cursor, main_menu_ui, stop_run_timer)
+                .in_schedule(OnEnter(GameState::MainMenu)),
+        )
+        .add_systems(
+            (
+                start_run_timer,
+                start_replay,
+                respawn_at_checkpoint.before(reset_safe_positions),
+                reset_safe_positions,
+            )
+                .in_schedule(OnEnter(GameState::Play)),
+        )
+        .add_system(finish_replay.in_schedule(OnEnter(GameState::MainMenu)))
+        .add_system(reset.in_schedule(OnExit(GameState::MainMenu)))
+        .add_system(menu_buttons.run_if(resource_exists::<LevelScenes>()))
+        .add_systems((
+            submenu_buttons.run_if(resource_exists::<GameAssets>()),
+            update_submenu_labels,
+            update_help_text.run_if(resource_exists::<CurrentLevel>()),
+        ))
+        .add_system(rebind_keys.after(toggle_pause))
+        .init_resource::<Rebinding>()
+        .add_startup_system(load_bindings)
+        .add_system(toggle_pause.in_set(OnUpdate(GameState::Play)))
+        .add_system(
+            gather_live_input
+                .in_base_set(CoreSet::PreUpdate)
+                .after(InputSystem)
+                .run_if(in_state(GameState::Play)),
+        )
+        .edit_schedule(gameplay_schedule.clone(), |schedule| {
+            schedule.configure_set(
+                GameplaySet
+                    .run_if(in_state(GameState::Play))
+                    .run_if(in_state(PauseState::Running)),
+            );
+        })
         .add_systems(
             (
+                read_frame_input,
+                player_look,
+                anvil_boost,
+                update_controller_state,
                 player_movement,
-                player_gravity,
-                player_jump,
                 player_hold,
                 reset_request,
+                recall_anvil,
                 check_reach_objective,
+                check_checkpoints,
                 anvil_held,
                 toggle_help,
+            )
+                .chain()
+                .in_set(GameplaySet)
+                .in_schedule(gameplay_schedule.clone()),
+        )
+        .add_systems(
+            (
+                check_falls,
+                update_safe_positions,
                 hit_events,
+                break_scenery,
+                despawn_fragments,
+                update_score_text,
+                update_launch_text,
+                update_particles,
+                tick_run_timer,
+                update_run_timer_text,
+                record_ghost,
+                move_ghost,
+            )
+                .chain()
+                .after(toggle_help)
+                .in_set(GameplaySet)
+                .in_schedule(gameplay_schedule),
+        )
+        .add_systems((reset, unpause).in_schedule(OnExit(GameState::Play)))
+        .add_system(pause_game.in_schedule(OnEnter(PauseState::Paused)))
+        .add_system(resume_game.in_schedule(OnExit(PauseState::Paused)))
+        .add_systems(
+            (
+                stop_run_timer,
+                finish_replay,
+                finish_level,
+                save_progress,
+                save_ghost,
+                level_complete_ui,
+                duck_level_complete,
             )
                 .chain()
-                .in_set(OnUpdate(GameState::Play)),
+                .in_schedule(OnEnter(GameState::LevelComplete)),
         )
-        .add_system(reset.in_schedule(OnExit(GameState::Play)))
+        .add_system(advance_level.in_set(OnUpdate(GameState::LevelComplete)))
+        .add_system(reset.in_schedule(OnExit(GameState::LevelComplete)))
+        .init_resource::<Campaign>()
+        .init_resource::<RunTimer>()
+        .init_resource::<Ghost>()
+        .init_resource::<GhostRecorder>()
+        .init_resource::<ColliderCache>()
+        .init_resource::<SceneLoading>()
+        .init_resource::<LoadingError>()
+        .add_startup_system(load_save)
         .init_resource::<AudioMixer>()
-        .insert_resource(DirectionalLightShadowMap { size: 4096 })
+        .add_startup_system(load_settings)
+        .add_system(apply_settings)
+        .add_system(apply_audio_settings.before(update_mixer))
+        .add_system(update_mixer)
         .insert_resource(AmbientLight {
             color: Color::WHITE,
             brightness: 1.0 / 5.0f32,
@@ -85,16 +287,27 @@ fn main() {
             objectives: vec![],
             powerups: vec![],
             help: true,
+            broken: vec![],
+            checkpoint: None,
         })
         .run();
 }
 
+/// Distance in meters from a checkpoint within which the player reaches it.
+const CHECKPOINT_RADIUS: f32 = 3.0;
+/// Where the anvil respawns from a checkpoint, the same offset as the default spawns.
+const CHECKPOINT_ANVIL_OFFSET: Vec3 = Vec3::new(0.0, 0.5, -2.0);
+
 #[derive(Clone, Eq, PartialEq, Debug, Hash, Default, States)]
 enum GameState {
     #[default]
     AssetLoading,
+    MainMenu,
     PrepareScene,
+    /// The level scene could not be prepared, see [`LoadingError`].
+    LoadingFailed,
     Play,
+    LevelComplete,
 }
 
 #[derive(AssetCollection, Resource)]
@@ -103,66 +316,28 @@ struct GameAssets {
     ost: Handle<AudioSource>,
     #[asset(path = "hit.ogg")]
     hit: Handle<AudioSource>,
-    #[asset(path = "testcity.gltf#Scene0")]
-    testcity: Handle<Scene>,
+    #[asset(path = "campaign.levels.ron")]
+    levels: Handle<LevelManifest>,
     #[asset(path = "anvil.gltf#Scene0")]
     anvil: Handle<Scene>,
     #[asset(path = "monof55.ttf")]
     font: Handle<Font>,
 }
 
-#[derive(Resource, Default, Debug)]
-struct AudioMixer {
-    ost: Handle<AudioSink>,
-}
+#[derive(Component)]
+struct HelpTag;
 
-fn soundtrack(game_assets: Res<GameAssets>, audio: Res<Audio>, mut mixer: ResMut<AudioMixer>) {
-    mixer.ost = audio.play_with_settings(
-        game_assets.ost.clone_weak(),
-        PlaybackSettings {
-            repeat: true,
-            ..default()
-        },
-    );
-}
+#[derive(Component)]
+struct HelpHint;
 
-fn hit_events(
-    mut contact_force_events: EventReader<ContactForceEvent>,
+fn player_ui(
+    mut commands: Commands,
     game_assets: Res<GameAssets>,
-    audio: Res<Audio>,
+    progress: Res<Progress>,
+    current_level: Res<CurrentLevel>,
+    info: Res<Info>,
+    bindings: Res<Bindings>,
 ) {
-    for contact_force_event in contact_force_events.iter() {
-        let vol = contact_force_event.total_force.length();
-        let vol = (vol / 10000.0).clamp(0.0, 1.0);
-        audio.play_with_settings(
-            game_assets.hit.clone_weak(),
-            PlaybackSettings {
-                volume: vol,
-                ..default()
-            },
-        );
-    }
-}
-
-const HELPTEXT: &str = "\
-Welcome, courier! This is the forge of Anvil Express.
-You have to deliver an anvil to three customers.
-
-Pick it up with [E].
-Hold [E] to be attracted to the anvil.
-Throw it with [Q] if you are not jumping.
-If you lose it, just press [Delete].
-
-The customers are identified by a blue light.
-Good luck!
-
-[H] to toggle this box.
-";
-
-#[derive(Component)]
-struct HelpTag;
-
-fn player_ui(mut commands: Commands, game_assets: Res<GameAssets>, progress: Res<Progress>) {
     commands
         .spawn((
             NodeBundle {
@@ -185,7 +360,7 @@ fn player_ui(mut commands: Commands, game_assets: Res<GameAssets>, progress: Res
             parent.spawn((
                 TextBundle {
                     text: Text::from_section(
-                        "[H] of help",
+                        format!("[{}] for help", bindings.key_name(Action::Help)),
                         TextStyle {
                             font: game_assets.font.clone(),
                             font_size: 30.0,
@@ -199,14 +374,28 @@ fn player_ui(mut commands: Commands, game_assets: Res<GameAssets>, progress: Res
                     },
                     ..default()
                 },
+                HelpHint,
                 Transient::default(),
             ));
         });
-    let status = if progress.objectives.len() == 3 {
-        "Congratulations, you have delivered all the anvils.".to_string()
-    } else {
-        format!("Anvils delivered {}/3", progress.objectives.len())
-    };
+    let level = &current_level.info;
+    let mut status = format!(
+        "Level {}: {} (par {:.0}s)\nAnvils delivered {}/{}",
+        current_level.index + 1,
+        level.name,
+        level.par_time,
+        progress.objectives.len(),
+        level.objectives
+    );
+    let unlocked = progress.powerups.last().and_then(|powerup| {
+        info.obj_info
+            .iter()
+            .find(|obj_info| &obj_info.powerup == powerup)
+    });
+    if let Some(obj_info) = unlocked {
+        status += " - ";
+        status += &obj_info.description;
+    }
     commands
         .spawn((
             NodeBundle {
@@ -246,6 +435,122 @@ fn player_ui(mut commands: Commands, game_assets: Res<GameAssets>, progress: Res
                 Transient::default(),
             ));
         });
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect::new(
+                        Val::Percent(75.0),
+                        Val::Percent(0.0),
+                        Val::Percent(0.0),
+                        Val::Percent(0.0),
+                    ),
+                    size: Size::new(Val::Percent(25.0), Val::Percent(40.0)),
+                    ..default()
+                },
+                ..default()
+            },
+            Transient::default(),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle {
+                    text: Text::from_section(
+                        "",
+                        TextStyle {
+                            font: game_assets.font.clone(),
+                            font_size: 30.0,
+                            color: Color::WHITE,
+                        },
+                    ),
+                    style: Style {
+                        size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                        border: UiRect::all(Val::Px(2.0)),
+                        ..default()
+                    },
+                    ..default()
+                },
+                RunTimerText,
+                Transient::default(),
+            ));
+        });
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect::new(
+                        Val::Percent(75.0),
+                        Val::Percent(0.0),
+                        Val::Percent(95.0),
+                        Val::Percent(0.0),
+                    ),
+                    size: Size::new(Val::Percent(25.0), Val::Percent(5.0)),
+                    ..default()
+                },
+                ..default()
+            },
+            Transient::default(),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle {
+                    text: Text::from_section(
+                        score_text(&progress),
+                        TextStyle {
+                            font: game_assets.font.clone(),
+                            font_size: 30.0,
+                            color: Color::WHITE,
+                        },
+                    ),
+                    style: Style {
+                        size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                        border: UiRect::all(Val::Px(2.0)),
+                        ..default()
+                    },
+                    ..default()
+                },
+                ScoreText,
+                Transient::default(),
+            ));
+        });
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect::new(
+                        Val::Percent(35.0),
+                        Val::Percent(0.0),
+                        Val::Percent(90.0),
+                        Val::Percent(0.0),
+                    ),
+                    size: Size::new(Val::Percent(30.0), Val::Percent(5.0)),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            Transient::default(),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle {
+                    text: Text::from_section(
+                        "",
+                        TextStyle {
+                            font: game_assets.font.clone(),
+                            font_size: 30.0,
+                            color: Color::rgb(1.0, 0.5, 0.1),
+                        },
+                    ),
+                    ..default()
+                },
+                LaunchText,
+                Transient::default(),
+            ));
+        });
     commands
         .spawn((
             NodeBundle {
@@ -274,7 +579,7 @@ fn player_ui(mut commands: Commands, game_assets: Res<GameAssets>, progress: Res
             parent.spawn((
                 TextBundle {
                     text: Text::from_section(
-                        HELPTEXT,
+                        help_text(&bindings, current_level.info.objectives),
                         TextStyle {
                             font: game_assets.font.clone(),
                             font_size: 30.0,
@@ -298,10 +603,10 @@ fn toggle_help(
     mut commands: Commands,
     mut progress: ResMut<Progress>,
     mut help_query: Query<(Entity, &HelpTag)>,
-    keys: Res<Input<KeyCode>>,
+    input: Res<FrameInput>,
 ) {
     if let Ok((ent, _)) = help_query.get_single_mut() {
-        if keys.just_pressed(KeyCode::H) {
+        if input.just_pressed(Action::Help) {
             if progress.help {
                 progress.help = false;
                 commands.entity(ent).insert(Visibility::Hidden);
@@ -313,6 +618,28 @@ fn toggle_help(
     }
 }
 
+fn update_help_text(
+    help_query: Query<&Children, With<HelpTag>>,
+    mut hint_query: Query<&mut Text, With<HelpHint>>,
+    mut text_query: Query<&mut Text, Without<HelpHint>>,
+    bindings: Res<Bindings>,
+    current_level: Res<CurrentLevel>,
+) {
+    if !bindings.is_changed() {
+        return;
+    }
+    for mut text in hint_query.iter_mut() {
+        text.sections[0].value = format!("[{}] for help", bindings.key_name(Action::Help));
+    }
+    for children in help_query.iter() {
+        for child in children.iter() {
+            if let Ok(mut text) = text_query.get_mut(*child) {
+                text.sections[0].value = help_text(&bindings, current_level.info.objectives);
+            }
+        }
+    }
+}
+
 fn grab_cursor(mut window_query: Query<&mut Window>) {
     if let Ok(mut window) = window_query.get_single_mut() {
         window.cursor.grab_mode = CursorGrabMode::Locked;
@@ -320,7 +647,11 @@ fn grab_cursor(mut window_query: Query<&mut Window>) {
     }
 }
 
-fn setup_graphics(mut commands: Commands, game_assets: Res<GameAssets>) {
+fn setup_graphics(
+    mut commands: Commands,
+    current_level: Res<CurrentLevel>,
+    scene_query: Query<(Entity, &LevelScene)>,
+) {
     commands.spawn((
         DirectionalLightBundle {
             directional_light: DirectionalLight {
@@ -339,77 +670,282 @@ fn setup_graphics(mut commands: Commands, game_assets: Res<GameAssets>) {
         },
         Transient::default(),
     ));
+    // the level geometry outlives the resets, only another level replaces it
+    if let Ok((ent, scene)) = scene_query.get_single() {
+        if scene.index == current_level.index {
+            return;
+        }
+        commands.entity(ent).despawn_recursive();
+    }
     commands.spawn((
         SceneBundle {
-            scene: game_assets.testcity.clone(),
+            scene: current_level.scene.clone(),
             transform: Transform::default().with_scale(Vec3::splat(1.0)),
             ..default()
         },
-        Transient::default(),
+        LevelScene {
+            index: current_level.index,
+        },
     ));
 }
 
+/// Root of the level scene, the one whose meshes get colliders and tags.
+#[derive(Component)]
+struct LevelScene {
+    index: usize,
+}
+
+/// What the tags of the level scene place on every reset, read once when it's prepared.
+#[derive(Component, Default)]
+struct LevelLayout {
+    player_spawn: Option<Vec3>,
+    anvil_spawn: Option<Vec3>,
+    objectives: Vec<(u32, Vec3)>,
+    checkpoints: Vec<(u32, Vec3)>,
+}
+
+impl LevelLayout {
+    fn place(
+        &self,
+        commands: &mut Commands,
+        level: &Level,
+        player_query: &mut Query<&mut Transform, (With<Player>, Without<Anvil>)>,
+        anvil_query: &mut Query<(Entity, &mut Transform), (With<Anvil>, Without<Player>)>,
+    ) {
+        let player_spawn = self.player_spawn.unwrap_or(level.player_spawn);
+        for mut tr in player_query.iter_mut() {
+            *tr = Transform::from_translation(player_spawn);
+        }
+        // held still until here, the colliders of the level weren't there to land on
+        let anvil_spawn = self.anvil_spawn.unwrap_or(level.anvil_spawn);
+        for (ent, mut tr) in anvil_query.iter_mut() {
+            *tr = Transform::from_translation(anvil_spawn);
+            commands
+                .entity(ent)
+                .insert((RigidBody::Dynamic, Velocity::zero()));
+        }
+        for (num, translation) in self.objectives.iter() {
+            commands.spawn((
+                TransformBundle {
+                    local: Transform::from_translation(*translation),
+                    ..default()
+                },
+                Objective { num: *num },
+                Transient::default(),
+            ));
+        }
+        for (order, translation) in self.checkpoints.iter() {
+            commands.spawn((
+                TransformBundle {
+                    local: Transform::from_translation(*translation),
+                    ..default()
+                },
+                Checkpoint { order: *order },
+                Transient::default(),
+            ));
+        }
+    }
+}
+
+/// Transform a breakable or prop of the level scene is put back to on every reset.
+#[derive(Component)]
+struct SceneRestState {
+    transform: Transform,
+}
+
+/// Drops a level scene that failed to load, so the next attempt spawns it again.
+fn unload_level_scene(mut commands: Commands, scene_query: Query<Entity, With<LevelScene>>) {
+    for ent in scene_query.iter() {
+        commands.entity(ent).despawn_recursive();
+    }
+}
+
+/// Restores the broken breakables and the moved props of the level scene kept from the last run.
+fn reset_level_scene(mut commands: Commands, query: Query<(Entity, &SceneRestState)>) {
+    for (ent, rest) in query.iter() {
+        commands
+            .entity(ent)
+            .insert((rest.transform, Velocity::zero(), Visibility::Inherited))
+            .remove::<ColliderDisabled>();
+    }
+}
+
 fn add_scene_colliders(
     mut commands: Commands,
-    scene_query: Query<(Entity, &SceneInstance)>,
+    scene_query: Query<(Entity, Option<&LevelLayout>), (With<LevelScene>, With<SceneInstance>)>,
     children: Query<&Children>,
-    has_mesh: Query<(&Transform, &GlobalTransform, &Handle<Mesh>)>,
+    transforms: Query<(&Transform, &GlobalTransform)>,
+    has_mesh: Query<(&Handle<Mesh>, &Aabb, Option<&Handle<StandardMaterial>>)>,
+    tag_query: Query<(Option<&Name>, Option<&GltfExtras>)>,
     has_name: Query<&Name>,
-    meshes: ResMut<Assets<Mesh>>,
+    parents: Query<&Parent>,
+    mut player_query: Query<&mut Transform, (With<Player>, Without<Anvil>)>,
+    mut anvil_query: Query<(Entity, &mut Transform), (With<Anvil>, Without<Player>)>,
+    meshes: Res<Assets<Mesh>>,
+    mut collider_cache: ResMut<ColliderCache>,
+    asset_server: Res<AssetServer>,
+    gltfs: Res<Assets<Gltf>>,
+    current_level: Res<CurrentLevel>,
     mut next_state: ResMut<NextState<GameState>>,
 ) {
-    if scene_query.is_empty() {
+    let Ok((scene, layout)) = scene_query.get_single() else {
         return;
-    }
-    // very ugly, wait for anvil + testcity
-    if children
-        .iter_descendants(scene_query.iter().next().unwrap().0)
-        .count()
-        < 2
-    {
+    };
+    if let Some(layout) = layout {
+        // kept from the last run, the colliders are already there
+        layout.place(
+            &mut commands,
+            &current_level.info,
+            &mut player_query,
+            &mut anvil_query,
+        );
+        next_state.set(GameState::Play);
         return;
     }
+    let gltf_path = current_level
+        .info
+        .scene
+        .split('#')
+        .next()
+        .unwrap_or_default();
+    let gltf = gltfs.get(&asset_server.get_handle(gltf_path));
+    let tags_of = |ent| {
+        tag_query
+            .get(ent)
+            .map(|(name, extras)| SceneTags::of_entity(name, extras))
+            .unwrap_or_default()
+    };
+    let mut layout = LevelLayout::default();
+    for descendant in children.iter_descendants(scene) {
+        let Ok((tr, gt)) = transforms.get(descendant) else {
+            continue;
+        };
+        let mesh = has_mesh.get(descendant).ok();
+        let tags = if mesh.is_some() {
+            // the mesh is named after the glTF mesh, its parent is the node
+            let node_tags = parents
+                .get(descendant)
+                .map(|parent| tags_of(parent.get()))
+                .unwrap_or_default();
+            node_tags.merge(tags_of(descendant))
+        } else if children
+            .get(descendant)
+            .is_ok_and(|node_children| node_children.iter().any(|child| has_mesh.contains(*child)))
+        {
+            // tagged along with its meshes
+            continue;
+        } else {
+            tags_of(descendant)
+        };
 
-    for (scene, _) in scene_query.iter() {
-        for descendant in children.iter_descendants(scene) {
-            if let Ok((transform, gt, mesh)) = has_mesh.get(descendant) {
-                let rapier_collider = Collider::from_bevy_mesh(
-                    meshes.get(mesh).unwrap(),
-                    &ComputedColliderShape::TriMesh,
-                )
-                .unwrap();
-                // ugly, but will do
-                if let Ok(name) = has_name.get(scene) {
-                    if name.as_ref() == "Anvil" {
-                        commands.entity(scene).insert(Transient::default());
-                        commands.entity(descendant).insert(Transient::default());
-                    }
-                } else {
-                    if let Ok(name) = has_name.get(descendant) {
-                        if let Some(num) = name.as_ref().split("Objective").skip(1).next() {
-                            commands.spawn((
-                                TransformBundle {
-                                    local: Transform::from_translation(gt.translation()),
-                                    ..default()
-                                },
-                                Objective {
-                                    num: num.parse().unwrap(),
-                                },
-                            ));
-                        }
-                    }
-                    commands.entity(descendant).insert(rapier_collider);
-                }
+        match tags.spawn {
+            Some(SpawnPoint::Player) => layout.player_spawn = Some(gt.translation()),
+            Some(SpawnPoint::Anvil) => layout.anvil_spawn = Some(gt.translation()),
+            None => {}
+        }
+        if let Some(num) = tags.objective {
+            if !layout.objectives.iter().any(|(other, _)| *other == num) {
+                layout.objectives.push((num, gt.translation()));
+            }
+        }
+        if let Some(order) = tags.checkpoint {
+            if !layout.checkpoints.iter().any(|(other, _)| *other == order) {
+                layout.checkpoints.push((order, gt.translation()));
+            }
+        }
+
+        let Some((mesh, aabb, material)) = mesh else {
+            continue;
+        };
+        let shape = match tags.collider {
+            Some(shape) if tags.dynamic && !shape.is_dynamic_friendly() => {
+                warn!(
+                    "{:?} is dynamic, using a convex hull instead of a {:?}",
+                    has_name.get(descendant).ok(),
+                    shape
+                );
+                ColliderShape::ConvexHull
             }
+            Some(shape) => shape,
+            None if tags.dynamic => ColliderShape::ConvexHull,
+            None => ColliderShape::Trimesh,
+        };
+        let Some(rapier_collider) =
+            collider_cache.get_or_build(current_level.index, mesh, shape, &meshes, aabb)
+        else {
+            if shape != ColliderShape::None {
+                error!(
+                    "Could not build a {:?} collider for {:?}",
+                    shape,
+                    has_name.get(descendant).ok()
+                );
+            }
+            continue;
+        };
+        if tags.kill_volume {
+            // a kinematic player only meets fixed sensors with every collision type active
+            commands.entity(descendant).insert((
+                rapier_collider,
+                Sensor,
+                ActiveCollisionTypes::all(),
+                KillVolume,
+            ));
+            continue;
+        }
+        let surface = tags.surface.unwrap_or_else(|| {
+            let node_names = [
+                Some(descendant),
+                parents.get(descendant).ok().map(|p| p.get()),
+            ]
+            .into_iter()
+            .flatten()
+            .filter_map(|ent| has_name.get(ent).ok())
+            .map(|name| name.as_str());
+            let material_name = material.and_then(|material| {
+                gltf?
+                    .named_materials
+                    .iter()
+                    .find(|(_, handle)| *handle == material)
+                    .map(|(name, _)| name.as_str())
+            });
+            scene_surface(node_names, material_name)
+        });
+        commands
+            .entity(descendant)
+            .insert((rapier_collider, surface));
+        if tags.dynamic {
+            commands.entity(descendant).insert(RigidBody::Dynamic);
+        }
+        if tags.dynamic || tags.breakable.is_some() {
+            commands
+                .entity(descendant)
+                .insert(SceneRestState { transform: *tr });
+        }
+        if let Some(threshold) = tags.breakable {
+            // rapier uses the lowest threshold of the pair, for breakables more fragile than the anvil
+            commands.entity(descendant).insert((
+                ActiveEvents::CONTACT_FORCE_EVENTS,
+                ContactForceEventThreshold(threshold),
+                Breakable { threshold },
+            ));
         }
     }
+    layout.place(
+        &mut commands,
+        &current_level.info,
+        &mut player_query,
+        &mut anvil_query,
+    );
+    commands.entity(scene).insert(layout);
     next_state.set(GameState::Play);
 }
 
 #[derive(Component, Default, Clone, Debug)]
 struct Player {
     speed: f32,
+    /// Meters per second.
     velocity: Vec3,
+    /// Upward speed of a jump, in m/s.
     jump_strenght: f32,
     pickup_distance: f32,
     cooldown: Timer,
@@ -419,6 +955,8 @@ struct Player {
 #[derive(Component, Default, Clone, Debug)]
 struct PlayerCamera {
     sensitivity: Vec3,
+    /// Look speed of the gamepad right stick, in radians per second.
+    stick_sensitivity: Vec2,
 }
 
 #[derive(Component, Default, Clone, Debug)]
@@ -435,11 +973,23 @@ struct Objective {
     num: u32,
 }
 
+/// Where the player respawns with the anvil once reached carrying it.
+#[derive(Component, Default, Clone, Debug)]
+struct Checkpoint {
+    /// Only a later checkpoint replaces the one reached.
+    order: u32,
+}
+
 #[derive(Resource, Default, Clone, Debug)]
 struct Progress {
     objectives: Vec<u32>,
     powerups: Vec<Powerup>,
     help: bool,
+    /// Breakables shattered in this level, each counted once however often the resets restore
+    /// it.
+    broken: Vec<Entity>,
+    /// Order of the last checkpoint reached in this level.
+    checkpoint: Option<u32>,
 }
 
 #[derive(Resource, Default, Clone, Debug)]
@@ -453,7 +1003,7 @@ struct ObjectiveInfo {
     powerup: Powerup,
 }
 
-#[derive(Component, Default, Clone, Debug, PartialEq)]
+#[derive(Component, Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
 enum Powerup {
     #[default]
     Strenght,
@@ -461,15 +1011,21 @@ enum Powerup {
     Speed,
 }
 
-fn spawn_player(mut commands: Commands, game_assets: Res<GameAssets>) {
+fn spawn_player(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    current_level: Res<CurrentLevel>,
+    settings: Res<Settings>,
+) {
     commands.spawn((
         Anvil::default(),
         Name::new("Anvil"),
         Velocity::default(),
-        RigidBody::Dynamic,
+        // dynamic once the level is placed, see LevelLayout::place
+        RigidBody::Fixed,
         SceneBundle {
             scene: game_assets.anvil.clone(),
-            transform: Transform::from_xyz(0.0, 12.5, 0.0),
+            transform: Transform::from_translation(current_level.info.anvil_spawn),
             ..default()
         },
         ColliderMassProperties::Density(10.0),
@@ -488,9 +1044,14 @@ fn spawn_player(mut commands: Commands, game_assets: Res<GameAssets>) {
             angular_damping: 10.0,
         },
         Sleeping::disabled(),
+        SafePosition::default(),
         Transient::default(),
-        ActiveEvents::CONTACT_FORCE_EVENTS,
-        ContactForceEventThreshold(3000.0),
+        // impact sounds and particles
+        (
+            ActiveEvents::CONTACT_FORCE_EVENTS,
+            ContactForceEventThreshold(3000.0),
+            Surface::Metal,
+        ),
     ));
     commands
         .spawn((
@@ -498,13 +1059,13 @@ fn spawn_player(mut commands: Commands, game_assets: Res<GameAssets>) {
             Player {
                 speed: 1.0,
                 velocity: Vec3::ZERO,
-                jump_strenght: 0.07,
+                jump_strenght: 7.0,
                 pickup_distance: 3.0,
                 cooldown: Timer::from_seconds(0.3, TimerMode::Once),
                 launched: false,
             },
             TransformBundle {
-                local: Transform::from_xyz(0.0, 12.0, 2.0),
+                local: Transform::from_translation(current_level.info.player_spawn),
                 ..default()
             },
             Velocity::default(),
@@ -517,8 +1078,11 @@ fn spawn_player(mut commands: Commands, game_assets: Res<GameAssets>) {
                     min_width: CharacterLength::Absolute(0.2),
                     include_dynamic_bodies: true,
                 }),
+                // walk into the kill volumes rather than on them
+                filter_flags: QueryFilterFlags::EXCLUDE_SENSORS,
                 ..default()
             },
+            Controller::default(),
             Collider::capsule(Vec3::new(0.0, 0.25, 0.0), Vec3::new(0.0, 1.5, 0.0), 0.25),
             CollisionGroups::new(Group::GROUP_1, Group::ALL),
             Transient::default(),
@@ -527,10 +1091,7 @@ fn spawn_player(mut commands: Commands, game_assets: Res<GameAssets>) {
             parent.spawn((
                 Camera3dBundle {
                     transform: Transform::from_xyz(0.0, 1.5, 0.0),
-                    projection: Projection::Perspective(PerspectiveProjection {
-                        fov: PI / 2.0,
-                        ..default()
-                    }),
+                    projection: settings.projection(),
                     camera: Camera {
                         hdr: true,
                         ..default()
@@ -538,112 +1099,15 @@ fn spawn_player(mut commands: Commands, game_assets: Res<GameAssets>) {
                     ..default()
                 },
                 BloomSettings {
-                    intensity: 0.1, // the default is 0.3
+                    intensity: settings.bloom,
                     ..default()
                 },
-                PlayerCamera {
-                    sensitivity: Vec3::new(0.004, 0.004, 1.0),
-                },
+                settings.player_camera(),
                 Transient::default(),
             ));
         });
 }
 
-fn player_movement(
-    mut player_query: Query<
-        (
-            &mut Player,
-            &mut Transform,
-            &mut KinematicCharacterController,
-        ),
-        Without<PlayerCamera>,
-    >,
-    anvil_held_query: Query<(Entity, &Anvil, &Held)>,
-    keys: Res<Input<KeyCode>>,
-    time: Res<Time>,
-    mut cam_query: Query<(&mut PlayerCamera, &mut Transform), Without<Player>>,
-    mut mouse_motion_events: EventReader<MouseMotion>,
-    progress: Res<Progress>,
-) {
-    if let Ok((mut player, mut tr, mut contr)) = player_query.get_single_mut() {
-        if let Ok((cam, mut cam_tr)) = cam_query.get_single_mut() {
-            for mov in mouse_motion_events.iter() {
-                let pitch = cam_tr.rotation.to_euler(EulerRot::XYZ).0;
-                let amt = -mov.delta.y * cam.sensitivity.y;
-                if pitch + amt > -PI / 2.0 && pitch + amt < PI / 2.0 {
-                    cam_tr.rotate_local_x(amt);
-                }
-                tr.rotate_y(-mov.delta.x * cam.sensitivity.y);
-            }
-
-            let mut acceleration = Vec3::new(0.0, 0.0, 0.0);
-            if keys.pressed(KeyCode::W) {
-                acceleration += tr.forward();
-            }
-            if keys.pressed(KeyCode::S) {
-                acceleration -= tr.forward();
-            }
-            if keys.pressed(KeyCode::D) {
-                acceleration += tr.right();
-            }
-            if keys.pressed(KeyCode::A) {
-                acceleration -= tr.right();
-            }
-            if acceleration.length_squared() > 1.0 {
-                acceleration = acceleration.normalize()
-            }
-            if progress.powerups.contains(&Powerup::Speed) {
-                acceleration *= 1.2;
-            }
-            acceleration *= player.speed;
-            if !anvil_held_query.is_empty() {
-                acceleration *= 0.5;
-            }
-            acceleration += Vec3::NEG_Y * 0.3;
-            player.velocity += acceleration * time.delta_seconds();
-
-            contr.translation = Some(player.velocity * time.delta_seconds() * 100.0);
-        }
-    }
-}
-
-fn player_gravity(
-    mut player_query: Query<(&mut Player, &KinematicCharacterControllerOutput)>,
-    anvil_held_query: Query<(Entity, &Anvil, &Held)>,
-) {
-    if let Ok((mut player, out)) = player_query.get_single_mut() {
-        if out.grounded {
-            player.velocity.y = 0.0;
-            player.velocity.x *= 0.9;
-            player.velocity.z *= 0.9;
-        } else if anvil_held_query.is_empty() {
-            player.velocity.x *= 0.93;
-            player.velocity.z *= 0.93;
-        } else {
-            player.velocity.x *= 0.99;
-            player.velocity.z *= 0.99;
-        }
-    }
-}
-
-fn player_jump(
-    mut player_query: Query<(&mut Player, &KinematicCharacterControllerOutput)>,
-    anvil_held_query: Query<(Entity, &Anvil, &Held)>,
-    keys: Res<Input<KeyCode>>,
-) {
-    if keys.pressed(KeyCode::Space) {
-        if let Ok((mut player, out)) = player_query.get_single_mut() {
-            let on_held_anvil = out
-                .collisions
-                .iter()
-                .any(|coll| anvil_held_query.get(coll.entity).is_ok());
-            if out.grounded && !on_held_anvil {
-                player.velocity.y = player.jump_strenght;
-            }
-        }
-    }
-}
-
 fn player_hold(
     mut commands: Commands,
     mut player_query: Query<
@@ -656,82 +1120,93 @@ fn player_hold(
         (Without<Player>, Without<PlayerCamera>),
     >,
     held_query: Query<&Held>,
-    keys: Res<Input<KeyCode>>,
-    time: Res<Time>,
+    input: Res<FrameInput>,
     progress: Res<Progress>,
 ) {
-    match (player_query.get_single_mut(), anvil_query.get_single_mut()) {
-        (Ok((mut player, tr_player, out)), Ok((anvil_ent, _anvil, tr_anvil, mut vel))) => {
-            player.cooldown.tick(time.delta());
-            if held_query.get(anvil_ent).is_err() {
-                if keys.pressed(KeyCode::E) && player.cooldown.finished() {
-                    let mut delta = tr_anvil.translation - (tr_player.translation + Vec3::Y);
-                    if delta.length_squared() < player.pickup_distance * player.pickup_distance {
-                        // pickup
-                        commands.entity(anvil_ent).insert((
-                            Held::default(),
-                            RigidBody::Fixed,
-                            CollisionGroups::new(Group::NONE, Group::NONE),
-                        ));
-                    } else {
-                        // attract
-                        //if player.velocity.length_squared() > 1.0 {
-                        //    delta = delta.normalize() / player.velocity.length_squared();
-                        //}
-                        let mut amt = 0.003;
-                        if progress.powerups.contains(&Powerup::Weight) {
-                            amt = 0.004;
+    if let (Ok((mut player, tr_player, out)), Ok((anvil_ent, _anvil, tr_anvil, mut vel))) =
+        (player_query.get_single_mut(), anvil_query.get_single_mut())
+    {
+        player.cooldown.tick(Duration::from_secs_f32(input.dt));
+        if held_query.get(anvil_ent).is_err() {
+            if input.pressed(Action::Grab) && player.cooldown.finished() {
+                let delta = tr_anvil.translation - (tr_player.translation + Vec3::Y);
+                if delta.length_squared() < player.pickup_distance * player.pickup_distance {
+                    // pickup
+                    commands.entity(anvil_ent).insert((
+                        Held::default(),
+                        RigidBody::Fixed,
+                        CollisionGroups::new(Group::NONE, Group::NONE),
+                    ));
+                } else {
+                    // attract
+                    //if player.velocity.length_squared() > 1.0 {
+                    //    delta = delta.normalize() / player.velocity.length_squared();
+                    //}
+                    // m/s² per meter away from the anvil
+                    let mut pull = 30.0;
+                    if progress.powerups.contains(&Powerup::Weight) {
+                        pull = 40.0;
+                    }
+                    // the anvil barely moves
+                    vel.linvel -= delta * pull * 0.002 * input.dt;
+                    player.velocity += delta * pull * input.dt;
+                    if !out.grounded
+                        && !player.launched
+                        && player.velocity.length() > CATAPULT_SPEED
+                    {
+                        // a kick like the boost's, not however far the pull happened to get
+                        let mut impulse = CATAPULT_IMPULSE;
+                        if progress.powerups.contains(&Powerup::Strenght) {
+                            impulse *= 1.2;
                         }
-                        amt *= time.delta_seconds() * 100.0;
-                        vel.linvel -= delta * amt * 0.2;
-                        player.velocity += delta * amt;
+                        player.velocity += delta.normalize_or_zero() * impulse;
+                        player.launched = true;
                     }
                 }
-            } else {
-                if keys.just_pressed(KeyCode::E) {
-                    // put down
-                    player.cooldown.reset();
-                    commands
-                        .entity(anvil_ent)
-                        .insert((
-                            RigidBody::Dynamic,
-                            Velocity {
-                                linvel: player.velocity * 100.0,
-                                ..default()
-                            },
-                            CollisionGroups::new(Group::GROUP_2, Group::ALL),
-                        ))
-                        .remove::<Held>();
-                } else if keys.pressed(KeyCode::Q) && !player.launched {
-                    // throw
-                    player.cooldown.reset();
-                    let on_held_anvil = out
-                        .collisions
-                        .iter()
-                        .any(|coll| held_query.get(coll.entity).is_ok());
-                    if out.grounded && !on_held_anvil {
-                        if let Ok((_, tr_cam)) = cam_query.get_single_mut() {
-                            let mut str = 10.0;
-                            if progress.powerups.contains(&Powerup::Strenght) {
-                                str = 12.0;
-                            }
-                            commands
-                                .entity(anvil_ent)
-                                .insert((
-                                    RigidBody::Dynamic,
-                                    Velocity {
-                                        linvel: player.velocity * 80.0 + tr_cam.forward() * str,
-                                        ..default()
-                                    },
-                                    CollisionGroups::new(Group::GROUP_2, Group::ALL),
-                                ))
-                                .remove::<Held>();
+            }
+        } else {
+            if input.just_pressed(Action::Grab) {
+                // put down
+                player.cooldown.reset();
+                commands
+                    .entity(anvil_ent)
+                    .insert((
+                        RigidBody::Dynamic,
+                        Velocity {
+                            linvel: player.velocity,
+                            ..default()
+                        },
+                        CollisionGroups::new(Group::GROUP_2, Group::ALL),
+                    ))
+                    .remove::<Held>();
+            } else if input.pressed(Action::Throw) && !player.launched {
+                // throw
+                player.cooldown.reset();
+                let on_held_anvil = out
+                    .collisions
+                    .iter()
+                    .any(|coll| held_query.get(coll.entity).is_ok());
+                if out.grounded && !on_held_anvil {
+                    if let Ok((_, tr_cam)) = cam_query.get_single_mut() {
+                        let mut str = 10.0;
+                        if progress.powerups.contains(&Powerup::Strenght) {
+                            str = 12.0;
                         }
+                        commands
+                            .entity(anvil_ent)
+                            .insert((
+                                RigidBody::Dynamic,
+                                Velocity {
+                                    linvel: player.velocity * 0.8 + tr_cam.forward() * str,
+                                    ..default()
+                                },
+                                CollisionGroups::new(Group::GROUP_2, Group::ALL),
+                            ))
+                            .remove::<Held>();
                     }
                 }
             }
         }
-        (_, _) => {}
     }
 }
 
@@ -739,18 +1214,17 @@ fn anvil_held(
     mut player_query: Query<(&Player, &Transform), Without<Anvil>>,
     mut anvil_query: Query<(&mut Anvil, &mut Transform, &Held), Without<Player>>,
 ) {
-    match (player_query.get_single_mut(), anvil_query.get_single_mut()) {
-        (Ok((_player, tr_player)), Ok((_anvil, mut tr_anvil, _held))) => {
-            let off = tr_player.forward() * 1.2 + tr_player.up() * 0.85;
-            tr_anvil.translation = tr_player.translation + off;
-            let mut angle = tr_player.rotation.to_euler(EulerRot::XYZ).1;
-            // i'm stupid, can't figure out why this is needed
-            if tr_player.forward().dot(Vec3::Z) > 0.0 {
-                angle = PI - angle;
-            }
-            tr_anvil.rotation = Quat::from_rotation_y(angle);
+    if let (Ok((_player, tr_player)), Ok((_anvil, mut tr_anvil, _held))) =
+        (player_query.get_single_mut(), anvil_query.get_single_mut())
+    {
+        let off = tr_player.forward() * 1.2 + tr_player.up() * 0.85;
+        tr_anvil.translation = tr_player.translation + off;
+        let mut angle = tr_player.rotation.to_euler(EulerRot::XYZ).1;
+        // i'm stupid, can't figure out why this is needed
+        if tr_player.forward().dot(Vec3::Z) > 0.0 {
+            angle = PI - angle;
         }
-        (_, _) => {}
+        tr_anvil.rotation = Quat::from_rotation_y(angle);
     }
 }
 
@@ -759,7 +1233,8 @@ fn check_reach_objective(
     anvil_query: Query<(&Anvil, &Transform), Without<Objective>>,
     mut next_state: ResMut<NextState<GameState>>,
     mut progress: ResMut<Progress>,
-    info: ResMut<Info>,
+    current_level: Res<CurrentLevel>,
+    mut run_timer: ResMut<RunTimer>,
 ) {
     if let Ok((_, tr_anvil)) = anvil_query.get_single() {
         for (obj, tr_current_obj) in obj_query.iter() {
@@ -768,45 +1243,80 @@ fn check_reach_objective(
             }
             let delta = tr_anvil.translation - (tr_current_obj.translation + Vec3::Y);
             if delta.length_squared() < 25.0 {
-                next_state.set(GameState::PrepareScene);
                 progress.objectives.push(obj.num);
-                // lol useful
-                if obj.num < 3 {
-                    progress
-                        .powerups
-                        .push(info.obj_info[obj.num as usize].powerup.clone());
+                let split = run_timer.elapsed;
+                run_timer.splits.push(split);
+                if let Some(powerup) = current_level.info.powerups.get(obj.num as usize) {
+                    progress.powerups.push(powerup.clone());
+                }
+                if progress.objectives.len() as u32 >= current_level.info.objectives {
+                    next_state.set(GameState::LevelComplete);
+                } else {
+                    next_state.set(GameState::PrepareScene);
                 }
             }
         }
     }
 }
 
-fn reset_request(keys: Res<Input<KeyCode>>, mut next_state: ResMut<NextState<GameState>>) {
-    if keys.just_pressed(KeyCode::Delete) {
-        next_state.set(GameState::PrepareScene);
+fn check_checkpoints(
+    checkpoint_query: Query<(&Checkpoint, &Transform), Without<Player>>,
+    player_query: Query<&Transform, With<Player>>,
+    held_query: Query<(), (With<Anvil>, With<Held>)>,
+    mut progress: ResMut<Progress>,
+) {
+    let Ok(tr_player) = player_query.get_single() else {
+        return;
+    };
+    if held_query.is_empty() {
+        return;
+    }
+    for (checkpoint, tr_checkpoint) in checkpoint_query.iter() {
+        let delta = tr_player.translation - tr_checkpoint.translation;
+        if delta.length_squared() < CHECKPOINT_RADIUS * CHECKPOINT_RADIUS
+            && progress
+                .checkpoint
+                .map_or(true, |reached| reached < checkpoint.order)
+        {
+            info!("Reached checkpoint {}", checkpoint.order);
+            progress.checkpoint = Some(checkpoint.order);
+        }
     }
 }
 
-fn cheat_powerup(keys: Res<Input<KeyCode>>, mut progress: ResMut<Progress>, info: ResMut<Info>) {
-    if keys.just_pressed(KeyCode::Key1) {
-        progress
-            .powerups
-            .push(info.obj_info[0 as usize].powerup.clone());
+/// Moves the player and the anvil to the last checkpoint reached, after the level spawns.
+fn respawn_at_checkpoint(
+    progress: Res<Progress>,
+    checkpoint_query: Query<(&Checkpoint, &Transform), (Without<Player>, Without<Anvil>)>,
+    mut player_query: Query<&mut Transform, (With<Player>, Without<Anvil>)>,
+    mut anvil_query: Query<&mut Transform, (With<Anvil>, Without<Player>)>,
+) {
+    let Some(order) = progress.checkpoint else {
+        return;
+    };
+    let Some((_, tr_checkpoint)) = checkpoint_query
+        .iter()
+        .find(|(checkpoint, _)| checkpoint.order == order)
+    else {
+        return;
+    };
+    for mut tr in player_query.iter_mut() {
+        tr.translation = tr_checkpoint.translation;
     }
-    if keys.just_pressed(KeyCode::Key2) {
-        progress
-            .powerups
-            .push(info.obj_info[1 as usize].powerup.clone());
+    for mut tr in anvil_query.iter_mut() {
+        tr.translation = tr_checkpoint.translation + CHECKPOINT_ANVIL_OFFSET;
     }
-    if keys.just_pressed(KeyCode::Key2) {
-        progress
-            .powerups
-            .push(info.obj_info[2 as usize].powerup.clone());
+}
+
+fn reset_request(input: Res<FrameInput>, mut next_state: ResMut<NextState<GameState>>) {
+    if input.just_pressed(Action::Reset) {
+        next_state.set(GameState::PrepareScene);
     }
 }
 
-fn reset(mut commands: Commands, query: Query<Entity, &Transient>) {
+fn reset(mut commands: Commands, query: Query<Entity, (With<Transient>, Without<Parent>)>) {
+    // despawn the roots recursively, or the spawned scenes would leak their children
     for ent in query.iter() {
-        commands.entity(ent).despawn()
+        commands.entity(ent).despawn_recursive()
     }
 }
@@ -0,0 +1,412 @@
+use bevy::{app::AppExit, prelude::*, window::CursorGrabMode};
+use bevy_rapier3d::prelude::*;
+
+use crate::{
+    bindings::{Action, Bindings, Rebinding},
+    gamepad::start_pressed,
+    grab_cursor,
+    level::{select_level, Campaign, LevelManifest, LevelScenes},
+    loading::LoadingError,
+    save::SaveData,
+    settings::{write_settings, Setting, Settings},
+    GameAssets, GameState, Progress, Transient,
+};
+
+/// Whether the game is paused, only meaningful in [`GameState::Play`].
+#[derive(Clone, Eq, PartialEq, Debug, Hash, Default, States)]
+pub enum PauseState {
+    #[default]
+    Running,
+    Paused,
+}
+
+#[derive(Component, Clone, Debug)]
+pub enum MenuButton {
+    Play,
+    Continue,
+    Resume,
+    MainMenu,
+    Controls,
+    Options,
+    Rebind(Action),
+    Setting(Setting),
+    Back,
+    Quit,
+}
+
+/// Root of every menu, to hide them while a submenu is open.
+#[derive(Component)]
+pub struct Menu;
+
+#[derive(Component)]
+pub struct PauseMenu;
+
+/// A menu opened on top of another one, e.g. the controls.
+#[derive(Component)]
+pub struct SubMenu;
+
+pub fn release_cursor(mut window_query: Query<&mut Window>) {
+    if let Ok(mut window) = window_query.get_single_mut() {
+        window.cursor.grab_mode = CursorGrabMode::None;
+        window.cursor.visible = true;
+    }
+}
+
+/// Spawns a full screen column of buttons, returning the root entity.
+fn spawn_menu<S: AsRef<str>>(
+    commands: &mut Commands,
+    font: &Handle<Font>,
+    title: &str,
+    buttons: &[(S, MenuButton)],
+) -> Entity {
+    let text_style = TextStyle {
+        font: font.clone(),
+        font_size: 30.0,
+        color: Color::WHITE,
+    };
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    // long menus go on in a second column
+                    flex_wrap: FlexWrap::Wrap,
+                    align_content: AlignContent::Center,
+                    gap: Size::all(Val::Px(10.0)),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.7).into(),
+                ..default()
+            },
+            Menu,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                title,
+                TextStyle {
+                    font_size: 60.0,
+                    ..text_style.clone()
+                },
+            ));
+            for (label, button) in buttons {
+                parent
+                    .spawn((
+                        ButtonBundle {
+                            style: Style {
+                                size: Size::new(Val::Px(300.0), Val::Px(50.0)),
+                                align_items: AlignItems::Center,
+                                justify_content: JustifyContent::Center,
+                                ..default()
+                            },
+                            background_color: Color::rgb(0.15, 0.15, 0.15).into(),
+                            ..default()
+                        },
+                        button.clone(),
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn(TextBundle::from_section(label.as_ref(), text_style.clone()));
+                    });
+            }
+        })
+        .id()
+}
+
+pub fn main_menu_ui(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    manifests: Res<Assets<LevelManifest>>,
+    save: Res<SaveData>,
+) {
+    commands.spawn((Camera2dBundle::default(), Transient::default()));
+    let mut buttons = vec![];
+    if let Some(manifest) = manifests.get(&game_assets.levels) {
+        if !save.completed_levels.is_empty() && save.next_level(manifest).is_some() {
+            buttons.push(("Continue", MenuButton::Continue));
+        }
+    }
+    buttons.push(("New game", MenuButton::Play));
+    buttons.push(("Controls", MenuButton::Controls));
+    buttons.push(("Options", MenuButton::Options));
+    buttons.push(("Quit", MenuButton::Quit));
+    let menu = spawn_menu(&mut commands, &game_assets.font, "Anvil Express", &buttons);
+    commands.entity(menu).insert(Transient::default());
+}
+
+pub fn loading_failed_ui(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    loading_error: Res<LoadingError>,
+) {
+    commands.spawn((Camera2dBundle::default(), Transient::default()));
+    let menu = spawn_menu(
+        &mut commands,
+        &game_assets.font,
+        "Loading failed",
+        &[("Main menu", MenuButton::MainMenu)],
+    );
+    let message = commands
+        .spawn(
+            TextBundle::from_section(
+                loading_error.0.clone(),
+                TextStyle {
+                    font: game_assets.font.clone(),
+                    font_size: 24.0,
+                    color: Color::rgb(1.0, 0.6, 0.6),
+                },
+            )
+            .with_text_alignment(TextAlignment::Center),
+        )
+        .id();
+    // under the title, above the button
+    commands
+        .entity(menu)
+        .insert_children(1, &[message])
+        .insert(Transient::default());
+}
+
+pub fn toggle_pause(
+    keys: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    pause_state: Res<State<PauseState>>,
+    mut next_pause_state: ResMut<NextState<PauseState>>,
+    rebinding: Res<Rebinding>,
+    window_query: Query<&mut Window>,
+) {
+    // while rebinding, [Escape] cancels instead
+    let pressed = keys.just_pressed(KeyCode::Escape) || start_pressed(&gamepads, &gamepad_buttons);
+    if pressed && rebinding.0.is_none() {
+        match pause_state.0 {
+            PauseState::Running => next_pause_state.set(PauseState::Paused),
+            PauseState::Paused => {
+                next_pause_state.set(PauseState::Running);
+                grab_cursor(window_query);
+            }
+        }
+    }
+}
+
+pub fn pause_game(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+    window_query: Query<&mut Window>,
+) {
+    rapier_config.physics_pipeline_active = false;
+    release_cursor(window_query);
+    let menu = spawn_menu(
+        &mut commands,
+        &game_assets.font,
+        "Paused",
+        &[
+            ("Resume", MenuButton::Resume),
+            ("Main menu", MenuButton::MainMenu),
+            ("Controls", MenuButton::Controls),
+            ("Options", MenuButton::Options),
+            ("Quit", MenuButton::Quit),
+        ],
+    );
+    commands.entity(menu).insert(PauseMenu);
+}
+
+pub fn resume_game(
+    mut commands: Commands,
+    mut rapier_config: ResMut<RapierConfiguration>,
+    menu_query: Query<Entity, With<PauseMenu>>,
+) {
+    rapier_config.physics_pipeline_active = true;
+    for ent in menu_query.iter() {
+        commands.entity(ent).despawn_recursive();
+    }
+}
+
+/// Leaving the level always unpauses, so the next one starts running.
+pub fn unpause(mut next_pause_state: ResMut<NextState<PauseState>>) {
+    next_pause_state.set(PauseState::Running);
+}
+
+pub fn menu_buttons(
+    mut commands: Commands,
+    mut interaction_query: Query<
+        (&Interaction, &MenuButton, &mut BackgroundColor),
+        Changed<Interaction>,
+    >,
+    game_assets: Res<GameAssets>,
+    manifests: Res<Assets<LevelManifest>>,
+    scenes: Res<LevelScenes>,
+    mut campaign: ResMut<Campaign>,
+    mut progress: ResMut<Progress>,
+    save: Res<SaveData>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut next_pause_state: ResMut<NextState<PauseState>>,
+    mut exit: EventWriter<AppExit>,
+    window_query: Query<&mut Window>,
+) {
+    let mut pressed = None;
+    for (interaction, button, mut color) in interaction_query.iter_mut() {
+        *color = match interaction {
+            Interaction::Clicked => {
+                pressed = Some(button.clone());
+                Color::rgb(0.35, 0.35, 0.35).into()
+            }
+            Interaction::Hovered => Color::rgb(0.25, 0.25, 0.25).into(),
+            Interaction::None => Color::rgb(0.15, 0.15, 0.15).into(),
+        };
+    }
+    match pressed {
+        Some(MenuButton::Play) | Some(MenuButton::Continue) => {
+            let Some(manifest) = manifests.get(&game_assets.levels) else {
+                return;
+            };
+            let (index, powerups) = match pressed {
+                Some(MenuButton::Continue) => (
+                    save.next_level(manifest).unwrap_or_default(),
+                    save.powerups.clone(),
+                ),
+                _ => (0, vec![]),
+            };
+            let Some(current_level) = select_level(manifest, index, &scenes) else {
+                return;
+            };
+            commands.insert_resource(current_level);
+            *campaign = Campaign {
+                powerups: powerups.clone(),
+                ..default()
+            };
+            *progress = Progress {
+                objectives: vec![],
+                powerups,
+                help: progress.help,
+                broken: vec![],
+                checkpoint: None,
+            };
+            next_state.set(GameState::PrepareScene);
+        }
+        Some(MenuButton::Resume) => {
+            next_pause_state.set(PauseState::Running);
+            grab_cursor(window_query);
+        }
+        Some(MenuButton::MainMenu) => {
+            next_pause_state.set(PauseState::Running);
+            next_state.set(GameState::MainMenu);
+        }
+        Some(MenuButton::Quit) => exit.send(AppExit),
+        _ => {}
+    }
+}
+
+fn binding_label(action: Action, bindings: &Bindings, rebinding: &Rebinding) -> String {
+    if rebinding.0 == Some(action) {
+        format!("{}: press a key", action.label())
+    } else {
+        format!("{}: {}", action.label(), bindings.key_name(action))
+    }
+}
+
+fn setting_label(setting: Setting, settings: &Settings) -> String {
+    format!("{}: {}", setting.label(), settings.value_text(setting))
+}
+
+/// Opens the controls and options menus on top of the current one, and edits their entries.
+pub fn submenu_buttons(
+    mut commands: Commands,
+    interaction_query: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    mut menu_query: Query<&mut Visibility, (With<Menu>, Without<SubMenu>)>,
+    submenu_query: Query<Entity, With<SubMenu>>,
+    game_assets: Res<GameAssets>,
+    bindings: Res<Bindings>,
+    mut rebinding: ResMut<Rebinding>,
+    mut settings: ResMut<Settings>,
+    state: Res<State<GameState>>,
+) {
+    for (interaction, button) in interaction_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        let (title, mut buttons): (&str, Vec<(String, MenuButton)>) = match button {
+            MenuButton::Controls => (
+                "Controls",
+                Action::ALL
+                    .iter()
+                    .map(|action| {
+                        (
+                            binding_label(*action, &bindings, &rebinding),
+                            MenuButton::Rebind(*action),
+                        )
+                    })
+                    .collect(),
+            ),
+            MenuButton::Options => (
+                "Options",
+                Setting::ALL
+                    .iter()
+                    .map(|setting| {
+                        (
+                            setting_label(*setting, &settings),
+                            MenuButton::Setting(*setting),
+                        )
+                    })
+                    .collect(),
+            ),
+            MenuButton::Rebind(action) => {
+                rebinding.0 = Some(*action);
+                continue;
+            }
+            MenuButton::Setting(setting) => {
+                settings.cycle(*setting);
+                write_settings(&settings);
+                continue;
+            }
+            MenuButton::Back => {
+                rebinding.0 = None;
+                for ent in submenu_query.iter() {
+                    commands.entity(ent).despawn_recursive();
+                }
+                for mut visibility in menu_query.iter_mut() {
+                    *visibility = Visibility::Visible;
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        for mut visibility in menu_query.iter_mut() {
+            *visibility = Visibility::Hidden;
+        }
+        buttons.push(("Back".to_string(), MenuButton::Back));
+        let menu = spawn_menu(&mut commands, &game_assets.font, title, &buttons);
+        commands.entity(menu).insert(SubMenu);
+        // cleaned up along with the menu it was opened from
+        if state.0 == GameState::Play {
+            commands.entity(menu).insert(PauseMenu);
+        } else {
+            commands.entity(menu).insert(Transient::default());
+        }
+    }
+}
+
+pub fn update_submenu_labels(
+    button_query: Query<(&MenuButton, &Children)>,
+    mut text_query: Query<&mut Text>,
+    bindings: Res<Bindings>,
+    rebinding: Res<Rebinding>,
+    settings: Res<Settings>,
+) {
+    if !bindings.is_changed() && !rebinding.is_changed() && !settings.is_changed() {
+        return;
+    }
+    for (button, children) in button_query.iter() {
+        let label = match button {
+            MenuButton::Rebind(action) => binding_label(*action, &bindings, &rebinding),
+            MenuButton::Setting(setting) => setting_label(*setting, &settings),
+            _ => continue,
+        };
+        for child in children.iter() {
+            if let Ok(mut text) = text_query.get_mut(*child) {
+                text.sections[0].value = label.clone();
+            }
+        }
+    }
+}
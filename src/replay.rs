@@ -0,0 +1,500 @@
+use bevy::{input::mouse::MouseMotion, prelude::*};
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bindings::{Action, Bindings},
+    gamepad::read_gamepads,
+    level::CurrentLevel,
+    menu::PauseState,
+    settings::Settings,
+    GameState, Powerup, Progress,
+};
+
+/// Length of a frame, for both physics and gameplay, while recording or replaying.
+pub const REPLAY_DT: f32 = 1.0 / 60.0;
+/// Version 2 records actions instead of keys, so replays don't depend on the bindings.
+const REPLAY_VERSION: u32 = 2;
+
+/// The systems stepping the gameplay, once a frame or with the physics in
+/// [`CoreSchedule::FixedUpdate`] while recording or replaying.
+#[derive(SystemSet, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct GameplaySet;
+
+/// The input the player systems read this frame, either live or from a replay.
+#[derive(Resource, Default, Clone, Debug)]
+pub struct FrameInput {
+    pub pressed: Vec<Action>,
+    pub just_pressed: Vec<Action>,
+    pub mouse_delta: Vec2,
+    /// Analog movement, x to the right and y forward.
+    pub movement: Vec2,
+    /// Analog look in stick units times seconds, x to the right and y up.
+    pub look: Vec2,
+    pub dt: f32,
+}
+
+impl FrameInput {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct ReplayFrame {
+    pub pressed: Vec<Action>,
+    pub just_pressed: Vec<Action>,
+    pub mouse_delta: Vec2,
+    #[serde(default)]
+    pub movement: Vec2,
+    #[serde(default)]
+    pub look: Vec2,
+}
+
+/// Live input gathered over the frames since the gameplay last stepped.
+#[derive(Resource, Default, Clone, Debug)]
+pub struct LiveInput(ReplayFrame);
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct ReplayFile {
+    pub version: u32,
+    pub level: String,
+    /// Powerups the run started with.
+    pub powerups: Vec<Powerup>,
+    pub dt: f32,
+    pub frames: Vec<ReplayFrame>,
+}
+
+#[derive(Default, Clone, Debug, PartialEq)]
+pub enum ReplayMode {
+    #[default]
+    Off,
+    Recording,
+    Playback,
+}
+
+#[derive(Resource, Default, Clone, Debug)]
+pub struct Replay {
+    pub mode: ReplayMode,
+    pub path: String,
+    pub file: ReplayFile,
+    /// Next frame to play back.
+    pub cursor: usize,
+    /// Whether the current level is being recorded or played back.
+    pub active: bool,
+}
+
+impl Replay {
+    /// Reads `--record <file>` or `--replay <file>` from the command line.
+    pub fn from_args() -> Self {
+        let args: Vec<String> = std::env::args().collect();
+        for pair in args.windows(2) {
+            match pair[0].as_str() {
+                "--record" => {
+                    return Self {
+                        mode: ReplayMode::Recording,
+                        path: pair[1].clone(),
+                        ..default()
+                    }
+                }
+                "--replay" => {
+                    return Self {
+                        mode: ReplayMode::Playback,
+                        path: pair[1].clone(),
+                        ..default()
+                    }
+                }
+                _ => {}
+            }
+        }
+        Self::default()
+    }
+
+    /// Whether the gameplay and the physics step by [`REPLAY_DT`] rather than once a frame.
+    pub fn fixed_timestep(&self) -> bool {
+        self.mode != ReplayMode::Off
+    }
+
+    /// The schedule [`GameplaySet`] runs in.
+    pub fn gameplay_schedule(&self) -> CoreSchedule {
+        if self.fixed_timestep() {
+            CoreSchedule::FixedUpdate
+        } else {
+            CoreSchedule::Main
+        }
+    }
+}
+
+const PHYSICS_SETS: [PhysicsSet; 4] = [
+    PhysicsSet::SyncBackend,
+    PhysicsSet::SyncBackendFlush,
+    PhysicsSet::StepSimulation,
+    PhysicsSet::Writeback,
+];
+
+/// Rapier, stepping once a frame or, with a fixed timestep, in [`CoreSchedule::FixedUpdate`]
+/// right after [`GameplaySet`].
+pub struct PhysicsPlugin {
+    pub fixed_timestep: bool,
+}
+
+impl Plugin for PhysicsPlugin {
+    fn build(&self, app: &mut App) {
+        let timestep_mode = if self.fixed_timestep {
+            TimestepMode::Fixed {
+                dt: REPLAY_DT,
+                substeps: 8,
+            }
+        } else {
+            TimestepMode::Variable {
+                max_dt: 1.0 / 20.0,
+                time_scale: 1.0,
+                substeps: 8,
+            }
+        };
+        app.insert_resource(RapierConfiguration {
+            timestep_mode,
+            ..default()
+        })
+        .add_plugin(
+            RapierPhysicsPlugin::<NoUserData>::default()
+                .with_default_system_setup(!self.fixed_timestep),
+        );
+        if !self.fixed_timestep {
+            return;
+        }
+        app.insert_resource(FixedTime::new_from_secs(REPLAY_DT))
+            .edit_schedule(CoreSchedule::FixedUpdate, |schedule| {
+                schedule.configure_sets(
+                    (
+                        PhysicsSet::SyncBackend,
+                        PhysicsSet::SyncBackendFlush,
+                        PhysicsSet::StepSimulation,
+                        PhysicsSet::Writeback,
+                    )
+                        .chain()
+                        .after(GameplaySet),
+                );
+                // stepping only along with the gameplay, the loading screens and menus last as
+                // long as they last and the replays would drift apart across them
+                for set in PHYSICS_SETS {
+                    schedule.configure_set(
+                        set.run_if(in_state(GameState::Play))
+                            .run_if(in_state(PauseState::Running)),
+                    );
+                }
+            })
+            .add_systems(
+                // what the gameplay spawned has to be there for the physics
+                (apply_system_buffers
+                    .after(GameplaySet)
+                    .before(PhysicsSet::SyncBackend),)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            );
+        for set in PHYSICS_SETS {
+            app.add_systems(
+                RapierPhysicsPlugin::<NoUserData>::get_systems(set.clone())
+                    .in_base_set(set)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            );
+        }
+    }
+}
+
+fn read_replay(path: &str) -> Result<ReplayFile, String> {
+    let contents = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let file: ReplayFile = ron::from_str(&contents).map_err(|err| err.to_string())?;
+    if file.version != REPLAY_VERSION {
+        return Err(format!("unsupported replay version {}", file.version));
+    }
+    Ok(file)
+}
+
+pub fn load_replay(mut replay: ResMut<Replay>) {
+    if replay.mode != ReplayMode::Playback {
+        return;
+    }
+    match read_replay(&replay.path) {
+        Ok(file) => replay.file = file,
+        Err(err) => {
+            error!("Could not load the replay {}: {}", replay.path, err);
+            replay.mode = ReplayMode::Off;
+        }
+    }
+}
+
+pub fn start_replay(
+    mut replay: ResMut<Replay>,
+    mut progress: ResMut<Progress>,
+    current_level: Res<CurrentLevel>,
+) {
+    if replay.active {
+        return;
+    }
+    match replay.mode {
+        ReplayMode::Recording => {
+            replay.file = ReplayFile {
+                version: REPLAY_VERSION,
+                level: current_level.info.name.clone(),
+                powerups: progress.powerups.clone(),
+                dt: REPLAY_DT,
+                frames: vec![],
+            };
+            replay.active = true;
+        }
+        ReplayMode::Playback => {
+            if replay.cursor == 0 && replay.file.level == current_level.info.name {
+                progress.powerups = replay.file.powerups.clone();
+                replay.active = true;
+            }
+        }
+        ReplayMode::Off => {}
+    }
+}
+
+pub fn finish_replay(mut replay: ResMut<Replay>) {
+    if !replay.active {
+        return;
+    }
+    replay.active = false;
+    replay.cursor = 0;
+    if replay.mode == ReplayMode::Recording {
+        let result = ron::ser::to_string(&replay.file)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| {
+                std::fs::write(&replay.path, contents).map_err(|err| err.to_string())
+            });
+        match result {
+            Ok(()) => info!("Replay saved to {}", replay.path),
+            Err(err) => error!("Could not save the replay {}: {}", replay.path, err),
+        }
+    }
+}
+
+/// Gathers the live input every frame, for the gameplay to take whenever it steps.
+pub fn gather_live_input(
+    keys: Res<Input<KeyCode>>,
+    bindings: Res<Bindings>,
+    mut mouse_motion_events: EventReader<MouseMotion>,
+    gamepads: Res<Gamepads>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_button_axes: Res<Axis<GamepadButton>>,
+    settings: Res<Settings>,
+    mut gamepad_pressed: Local<Vec<Action>>,
+    time: Res<Time>,
+    pause_state: Res<State<PauseState>>,
+    mut live: ResMut<LiveInput>,
+) {
+    if pause_state.0 == PauseState::Paused {
+        // don't look around with the motion made in the pause menu
+        mouse_motion_events.clear();
+        live.0 = default();
+        return;
+    }
+    let pad = read_gamepads(
+        &gamepads,
+        &gamepad_axes,
+        &gamepad_buttons,
+        &gamepad_button_axes,
+        &settings.gamepad_config(),
+    );
+    let live = &mut live.0;
+    live.pressed = Action::ALL
+        .into_iter()
+        .filter(|action| keys.pressed(bindings.key(*action)) || pad.pressed.contains(action))
+        .collect();
+    for action in Action::ALL {
+        let just_pressed = keys.just_pressed(bindings.key(action))
+            || (pad.pressed.contains(&action) && !gamepad_pressed.contains(&action));
+        if just_pressed && !live.just_pressed.contains(&action) {
+            live.just_pressed.push(action);
+        }
+    }
+    live.mouse_delta += mouse_motion_events
+        .iter()
+        .map(|motion| motion.delta)
+        .sum::<Vec2>();
+    live.movement = pad.movement;
+    live.look += pad.look * time.delta_seconds();
+    *gamepad_pressed = pad.pressed;
+}
+
+/// Steps the input, taking what was gathered live since the last step, or the next frame of
+/// the replay.
+pub fn read_frame_input(
+    time: Res<Time>,
+    mut live: ResMut<LiveInput>,
+    mut replay: ResMut<Replay>,
+    mut input: ResMut<FrameInput>,
+) {
+    let dt = match replay.mode {
+        ReplayMode::Off => time.delta_seconds(),
+        _ => REPLAY_DT,
+    };
+    // the held actions and the stick carry over to the steps in the same frame
+    let live = ReplayFrame {
+        pressed: live.0.pressed.clone(),
+        just_pressed: std::mem::take(&mut live.0.just_pressed),
+        mouse_delta: std::mem::take(&mut live.0.mouse_delta),
+        movement: live.0.movement,
+        look: std::mem::take(&mut live.0.look),
+    };
+    let frame = match (&replay.mode, replay.active) {
+        (ReplayMode::Recording, true) => {
+            replay.file.frames.push(live.clone());
+            live
+        }
+        (ReplayMode::Playback, true) => match replay.file.frames.get(replay.cursor).cloned() {
+            Some(frame) => {
+                replay.cursor += 1;
+                frame
+            }
+            None => {
+                info!("Replay finished, switching to live input");
+                replay.active = false;
+                live
+            }
+        },
+        _ => live,
+    };
+    *input = FrameInput {
+        pressed: frame.pressed,
+        just_pressed: frame.just_pressed,
+        mouse_delta: frame.mouse_delta,
+        movement: frame.movement,
+        look: frame.look,
+        dt,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::scene::SceneSpawner;
+
+    use super::*;
+
+    const SPAWN: Vec3 = Vec3::new(0.0, 2.0, 0.0);
+
+    #[derive(Component)]
+    struct Ball;
+
+    /// Stands in for the player systems, the input pushes the ball around.
+    fn push_ball(mut ball_query: Query<&mut Velocity, With<Ball>>, input: Res<FrameInput>) {
+        for mut vel in ball_query.iter_mut() {
+            vel.linvel.x += input.movement.x * 10.0 * input.dt;
+            if input.just_pressed(Action::Jump) {
+                vel.linvel.y = 5.0;
+            }
+        }
+    }
+
+    fn app(replay: Replay) -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin::default())
+            .add_asset::<Mesh>()
+            .init_resource::<SceneSpawner>()
+            .add_plugin(PhysicsPlugin {
+                fixed_timestep: true,
+            })
+            .insert_resource(State(GameState::Play))
+            .insert_resource(State(PauseState::Running))
+            .insert_resource(replay)
+            .init_resource::<LiveInput>()
+            .init_resource::<FrameInput>()
+            .edit_schedule(CoreSchedule::FixedUpdate, |schedule| {
+                schedule.configure_set(GameplaySet.run_if(in_state(GameState::Play)));
+            })
+            .add_systems(
+                (read_frame_input, push_ball)
+                    .chain()
+                    .in_set(GameplaySet)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            );
+        app.world.spawn((
+            TransformBundle::from_transform(Transform::from_xyz(0.0, -0.5, 0.0)),
+            Collider::cuboid(50.0, 0.5, 50.0),
+        ));
+        app.world.spawn((
+            TransformBundle::from_transform(Transform::from_translation(SPAWN)),
+            RigidBody::Dynamic,
+            Collider::ball(0.5),
+            Velocity::zero(),
+            Ball,
+        ));
+        app
+    }
+
+    fn live_frames() -> Vec<ReplayFrame> {
+        (0..120)
+            .map(|i| ReplayFrame {
+                movement: Vec2::new(if i % 40 < 20 { 1.0 } else { -0.5 }, 0.0),
+                just_pressed: if i % 30 == 0 {
+                    vec![Action::Jump]
+                } else {
+                    vec![]
+                },
+                ..default()
+            })
+            .collect()
+    }
+
+    /// Plays the frames, with a delivery halfway putting the ball back while the next scene
+    /// takes `loading_steps` fixed steps to prepare. Returns where the ball ends up.
+    fn run(app: &mut App, frames: &[ReplayFrame], loading_steps: usize) -> Vec3 {
+        let (before, after) = frames.split_at(frames.len() / 2);
+        let play = |app: &mut App, frames: &[ReplayFrame]| {
+            for frame in frames {
+                app.world.resource_mut::<LiveInput>().0 = frame.clone();
+                app.world.run_schedule(CoreSchedule::FixedUpdate);
+            }
+        };
+        play(app, before);
+        app.world.insert_resource(State(GameState::PrepareScene));
+        let mut ball_query = app
+            .world
+            .query_filtered::<(&mut Transform, &mut Velocity), With<Ball>>();
+        let (mut tr, mut vel) = ball_query.single_mut(&mut app.world);
+        *tr = Transform::from_translation(SPAWN);
+        *vel = Velocity::zero();
+        for _ in 0..loading_steps {
+            app.world.run_schedule(CoreSchedule::FixedUpdate);
+        }
+        app.world.insert_resource(State(GameState::Play));
+        play(app, after);
+        let mut ball_query = app.world.query_filtered::<&Transform, With<Ball>>();
+        ball_query.single(&app.world).translation
+    }
+
+    #[test]
+    fn replays_across_a_delivery() {
+        let frames = live_frames();
+        let mut recording = app(Replay {
+            mode: ReplayMode::Recording,
+            active: true,
+            ..default()
+        });
+        let recorded_end = run(&mut recording, &frames, 3);
+        let file = recording.world.resource::<Replay>().file.clone();
+        assert_eq!(file.frames.len(), frames.len());
+
+        // the level loads slower this time, with no live input
+        let mut playback = app(Replay {
+            mode: ReplayMode::Playback,
+            file,
+            active: true,
+            ..default()
+        });
+        let played_end = run(&mut playback, &vec![default(); frames.len()], 40);
+        assert_eq!(played_end, recorded_end);
+        assert!(
+            recorded_end.distance(SPAWN) > 0.1,
+            "the input moved the ball"
+        );
+    }
+}
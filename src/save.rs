@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    level::{CurrentLevel, LevelManifest},
+    timer::RunTimer,
+    Powerup, Progress,
+};
+
+/// Bump when the layout of [`SaveData`] changes, and migrate in [`load_save`].
+const SAVE_VERSION: u32 = 2;
+const SAVE_KEY: &str = "save";
+
+/// Campaign progress persisted across sessions.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+pub struct SaveData {
+    pub version: u32,
+    pub completed_levels: Vec<String>,
+    pub powerups: Vec<Powerup>,
+    /// Best completion time in seconds, by level name.
+    pub best_times: HashMap<String, f32>,
+    /// Splits of the best run, by level name. Added in version 2.
+    #[serde(default)]
+    pub best_splits: HashMap<String, Vec<f32>>,
+}
+
+impl Default for SaveData {
+    fn default() -> Self {
+        Self {
+            version: SAVE_VERSION,
+            completed_levels: vec![],
+            powerups: vec![],
+            best_times: HashMap::new(),
+            best_splits: HashMap::new(),
+        }
+    }
+}
+
+impl SaveData {
+    /// The first level of the manifest that hasn't been completed yet.
+    pub fn next_level(&self, manifest: &LevelManifest) -> Option<usize> {
+        manifest
+            .levels
+            .iter()
+            .position(|level| !self.completed_levels.contains(&level.name))
+    }
+}
+
+#[derive(Deserialize)]
+struct SaveHeader {
+    version: u32,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn storage_path(key: &str) -> Option<std::path::PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("anvil-express").join(format!("{key}.ron")))
+}
+
+/// Reads the entry `key` from the platform storage: a file in the data dir, or `localStorage` on the web.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read_storage(key: &str) -> Option<String> {
+    std::fs::read_to_string(storage_path(key)?).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write_storage(key: &str, contents: &str) -> Result<(), String> {
+    let path = storage_path(key).ok_or("no data directory on this platform")?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    }
+    std::fs::write(path, contents).map_err(|err| err.to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn read_storage(key: &str) -> Option<String> {
+    local_storage()?
+        .get_item(&format!("anvil-express.{key}"))
+        .ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn write_storage(key: &str, contents: &str) -> Result<(), String> {
+    local_storage()
+        .ok_or("localStorage is not available")?
+        .set_item(&format!("anvil-express.{key}"), contents)
+        .map_err(|err| format!("{err:?}"))
+}
+
+/// Reads `what` from the ron entry `key`, the defaults when it's missing or unreadable.
+pub fn read_ron<T: DeserializeOwned + Default>(key: &str, what: &str) -> T {
+    match read_storage(key) {
+        Some(contents) => ron::from_str(&contents).unwrap_or_else(|err| {
+            warn!("{} is corrupted, using the defaults: {}", what, err);
+            T::default()
+        }),
+        None => T::default(),
+    }
+}
+
+/// Writes `what` to the ron entry `key`, pretty unless it's bulky.
+pub fn write_ron<T: Serialize>(key: &str, value: &T, what: &str, pretty: bool) {
+    let contents = if pretty {
+        ron::ser::to_string_pretty(value, default())
+    } else {
+        ron::to_string(value)
+    };
+    let result = contents
+        .map_err(|err| err.to_string())
+        .and_then(|contents| write_storage(key, &contents));
+    if let Err(err) = result {
+        error!("Could not save {}: {}", what, err);
+    }
+}
+
+fn parse_save(contents: &str) -> Result<SaveData, String> {
+    let header: SaveHeader = ron::from_str(contents).map_err(|err| err.to_string())?;
+    match header.version {
+        // version 1 only lacks the best splits, which default to empty
+        1 | SAVE_VERSION => {
+            let mut save: SaveData = ron::from_str(contents).map_err(|err| err.to_string())?;
+            save.version = SAVE_VERSION;
+            Ok(save)
+        }
+        version => Err(format!("unsupported save version {version}")),
+    }
+}
+
+pub fn load_save(mut commands: Commands) {
+    let save = match read_storage(SAVE_KEY) {
+        Some(contents) => match parse_save(&contents) {
+            Ok(save) => save,
+            Err(err) => {
+                // keep the unreadable save around instead of overwriting it
+                warn!("Save file is corrupted, starting a new one: {}", err);
+                if let Err(err) = write_storage(&format!("{SAVE_KEY}.corrupt"), &contents) {
+                    error!("Could not back up the corrupted save: {}", err);
+                }
+                SaveData::default()
+            }
+        },
+        None => SaveData::default(),
+    };
+    commands.insert_resource(save);
+}
+
+pub fn write_save(save: &SaveData) {
+    write_ron(SAVE_KEY, save, "the save", true);
+}
+
+/// Records the level that was just completed.
+pub fn save_progress(
+    mut save: ResMut<SaveData>,
+    progress: Res<Progress>,
+    current_level: Res<CurrentLevel>,
+    run_timer: Res<RunTimer>,
+) {
+    let name = &current_level.info.name;
+    if !save.completed_levels.contains(name) {
+        save.completed_levels.push(name.clone());
+    }
+    for powerup in progress.powerups.iter() {
+        if !save.powerups.contains(powerup) {
+            save.powerups.push(powerup.clone());
+        }
+    }
+    let best = save.best_times.get(name).copied().unwrap_or(f32::MAX);
+    if run_timer.elapsed <= best {
+        save.best_times.insert(name.clone(), run_timer.elapsed);
+        save.best_splits
+            .insert(name.clone(), run_timer.splits.clone());
+    }
+    write_save(&save);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_v1_saves() {
+        let v1 = r#"(
+            version: 1,
+            completed_levels: ["Test city"],
+            powerups: [Weight],
+            best_times: {"Test city": 83.5},
+        )"#;
+        let save = parse_save(v1).unwrap();
+        assert_eq!(save.version, SAVE_VERSION);
+        assert_eq!(save.completed_levels, vec!["Test city".to_string()]);
+        assert_eq!(save.powerups, vec![Powerup::Weight]);
+        assert_eq!(save.best_times.get("Test city"), Some(&83.5));
+        assert!(save.best_splits.is_empty());
+    }
+
+    #[test]
+    fn reads_current_saves() {
+        let mut save = SaveData::default();
+        save.best_splits
+            .insert("Test city".to_string(), vec![20.0, 50.0]);
+        let contents = ron::to_string(&save).unwrap();
+        let parsed = parse_save(&contents).unwrap();
+        assert_eq!(parsed.best_splits, save.best_splits);
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let v3 = "(version: 3, completed_levels: [], powerups: [], best_times: {})";
+        assert!(parse_save(v3).is_err());
+        assert!(parse_save("not a save").is_err());
+    }
+}
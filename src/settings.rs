@@ -0,0 +1,239 @@
+use bevy::{core_pipeline::bloom::BloomSettings, pbr::DirectionalLightShadowMap, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    gamepad::GamepadConfig,
+    save::{read_ron, write_ron},
+    PlayerCamera,
+};
+
+const SETTINGS_KEY: &str = "settings";
+
+/// Options of the settings menu, persisted in the `settings` storage entry.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Settings {
+    /// Radians per pixel of mouse motion.
+    pub mouse_sensitivity: f32,
+    /// Radians per second of the gamepad right stick, horizontally.
+    pub stick_sensitivity: f32,
+    /// Dead zones of the gamepad sticks, as a fraction of their range.
+    pub move_dead_zone: f32,
+    pub look_dead_zone: f32,
+    /// How far the gamepad triggers are pulled before they count as pressed.
+    pub trigger_threshold: f32,
+    /// Vertical field of view in degrees.
+    pub fov: f32,
+    pub bloom: f32,
+    pub shadow_map_size: usize,
+    pub master_volume: f32,
+    pub music_volume: f32,
+    pub sfx_volume: f32,
+    pub muted: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            mouse_sensitivity: 0.004,
+            stick_sensitivity: 3.0,
+            move_dead_zone: 0.15,
+            look_dead_zone: 0.1,
+            trigger_threshold: 0.3,
+            fov: 90.0,
+            bloom: 0.1,
+            shadow_map_size: 4096,
+            master_volume: 1.0,
+            music_volume: 1.0,
+            sfx_volume: 1.0,
+            muted: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Setting {
+    MouseSensitivity,
+    StickSensitivity,
+    MoveDeadZone,
+    LookDeadZone,
+    TriggerThreshold,
+    Fov,
+    Bloom,
+    Shadows,
+    MasterVolume,
+    MusicVolume,
+    SfxVolume,
+    Mute,
+}
+
+impl Setting {
+    pub const ALL: [Setting; 12] = [
+        Setting::MouseSensitivity,
+        Setting::StickSensitivity,
+        Setting::MoveDeadZone,
+        Setting::LookDeadZone,
+        Setting::TriggerThreshold,
+        Setting::Fov,
+        Setting::Bloom,
+        Setting::Shadows,
+        Setting::MasterVolume,
+        Setting::MusicVolume,
+        Setting::SfxVolume,
+        Setting::Mute,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Setting::MouseSensitivity => "Mouse",
+            Setting::StickSensitivity => "Stick",
+            Setting::MoveDeadZone => "Move zone",
+            Setting::LookDeadZone => "Look zone",
+            Setting::TriggerThreshold => "Triggers",
+            Setting::Fov => "Fov",
+            Setting::Bloom => "Bloom",
+            Setting::Shadows => "Shadows",
+            Setting::MasterVolume => "Volume",
+            Setting::MusicVolume => "Music",
+            Setting::SfxVolume => "Sounds",
+            Setting::Mute => "Mute",
+        }
+    }
+
+    /// The values a click on the setting cycles through.
+    fn choices(&self) -> &'static [f32] {
+        match self {
+            Setting::MouseSensitivity => &[0.002, 0.003, 0.004, 0.005, 0.006, 0.008],
+            Setting::StickSensitivity => &[1.5, 2.0, 2.5, 3.0, 4.0, 5.0],
+            Setting::MoveDeadZone | Setting::LookDeadZone => &[0.05, 0.1, 0.15, 0.2, 0.25, 0.3],
+            Setting::TriggerThreshold => &[0.1, 0.2, 0.3, 0.5, 0.7],
+            Setting::Fov => &[70.0, 80.0, 90.0, 100.0, 110.0],
+            Setting::Bloom => &[0.0, 0.1, 0.2, 0.3],
+            Setting::Shadows => &[1024.0, 2048.0, 4096.0, 8192.0],
+            Setting::MasterVolume | Setting::MusicVolume | Setting::SfxVolume => {
+                &[0.0, 0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9, 1.0]
+            }
+            Setting::Mute => &[0.0, 1.0],
+        }
+    }
+}
+
+impl Settings {
+    fn get(&self, setting: Setting) -> f32 {
+        match setting {
+            Setting::MouseSensitivity => self.mouse_sensitivity,
+            Setting::StickSensitivity => self.stick_sensitivity,
+            Setting::MoveDeadZone => self.move_dead_zone,
+            Setting::LookDeadZone => self.look_dead_zone,
+            Setting::TriggerThreshold => self.trigger_threshold,
+            Setting::Fov => self.fov,
+            Setting::Bloom => self.bloom,
+            Setting::Shadows => self.shadow_map_size as f32,
+            Setting::MasterVolume => self.master_volume,
+            Setting::MusicVolume => self.music_volume,
+            Setting::SfxVolume => self.sfx_volume,
+            Setting::Mute => self.muted as u8 as f32,
+        }
+    }
+
+    fn set(&mut self, setting: Setting, value: f32) {
+        match setting {
+            Setting::MouseSensitivity => self.mouse_sensitivity = value,
+            Setting::StickSensitivity => self.stick_sensitivity = value,
+            Setting::MoveDeadZone => self.move_dead_zone = value,
+            Setting::LookDeadZone => self.look_dead_zone = value,
+            Setting::TriggerThreshold => self.trigger_threshold = value,
+            Setting::Fov => self.fov = value,
+            Setting::Bloom => self.bloom = value,
+            Setting::Shadows => self.shadow_map_size = value as usize,
+            Setting::MasterVolume => self.master_volume = value,
+            Setting::MusicVolume => self.music_volume = value,
+            Setting::SfxVolume => self.sfx_volume = value,
+            Setting::Mute => self.muted = value > 0.0,
+        }
+    }
+
+    /// Moves to the next choice of the setting, wrapping around to the first.
+    pub fn cycle(&mut self, setting: Setting) {
+        let current = self.get(setting);
+        let choices = setting.choices();
+        let next = choices
+            .iter()
+            .find(|choice| **choice > current + f32::EPSILON)
+            .unwrap_or(&choices[0]);
+        self.set(setting, *next);
+    }
+
+    pub fn value_text(&self, setting: Setting) -> String {
+        let value = self.get(setting);
+        match setting {
+            Setting::MouseSensitivity => format!("{:.3}", value),
+            Setting::StickSensitivity => format!("{:.1}", value),
+            Setting::Fov => format!("{:.0} deg", value),
+            Setting::Bloom if value == 0.0 => "Off".to_string(),
+            Setting::Bloom => format!("{:.1}", value),
+            Setting::Shadows => format!("{}", self.shadow_map_size),
+            Setting::MoveDeadZone
+            | Setting::LookDeadZone
+            | Setting::TriggerThreshold
+            | Setting::MasterVolume
+            | Setting::MusicVolume
+            | Setting::SfxVolume => format!("{:.0}%", value * 100.0),
+            Setting::Mute if self.muted => "On".to_string(),
+            Setting::Mute => "Off".to_string(),
+        }
+    }
+
+    pub fn player_camera(&self) -> PlayerCamera {
+        PlayerCamera {
+            sensitivity: Vec3::new(self.mouse_sensitivity, self.mouse_sensitivity, 1.0),
+            // the stick looks up and down slower than around
+            stick_sensitivity: Vec2::new(
+                self.stick_sensitivity,
+                self.stick_sensitivity * 2.0 / 3.0,
+            ),
+        }
+    }
+
+    pub fn gamepad_config(&self) -> GamepadConfig {
+        GamepadConfig {
+            move_dead_zone: self.move_dead_zone,
+            look_dead_zone: self.look_dead_zone,
+            trigger_threshold: self.trigger_threshold,
+        }
+    }
+
+    pub fn projection(&self) -> Projection {
+        Projection::Perspective(PerspectiveProjection {
+            fov: self.fov.to_radians(),
+            ..default()
+        })
+    }
+}
+
+pub fn load_settings(mut commands: Commands) {
+    commands.insert_resource(read_ron::<Settings>(SETTINGS_KEY, "The settings file"));
+}
+
+pub fn write_settings(settings: &Settings) {
+    write_ron(SETTINGS_KEY, settings, "the settings", true);
+}
+
+/// Applies changed settings to the entities already spawned, the audio ones go through the mixer.
+pub fn apply_settings(
+    settings: Res<Settings>,
+    mut camera_query: Query<(&mut PlayerCamera, &mut Projection, &mut BloomSettings)>,
+    mut shadow_map: ResMut<DirectionalLightShadowMap>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    for (mut cam, mut projection, mut bloom) in camera_query.iter_mut() {
+        *cam = settings.player_camera();
+        *projection = settings.projection();
+        bloom.intensity = settings.bloom;
+    }
+    if shadow_map.size != settings.shadow_map_size {
+        shadow_map.size = settings.shadow_map_size;
+    }
+}
@@ -0,0 +1,188 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{replay::FrameInput, Transient};
+
+/// What a collider is made of, picks the impact sound and particles.
+#[derive(Component, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Surface {
+    Metal,
+    #[default]
+    Concrete,
+    Glass,
+    Wood,
+}
+
+impl Surface {
+    pub const ALL: [Surface; 4] = [
+        Surface::Metal,
+        Surface::Concrete,
+        Surface::Glass,
+        Surface::Wood,
+    ];
+
+    /// Guesses the surface from a node or material name, e.g. `Wall` or `glass.001`.
+    pub fn from_name(name: &str) -> Option<Surface> {
+        let name = name.to_lowercase();
+        let has = |keywords: &[&str]| keywords.iter().any(|keyword| name.contains(keyword));
+        if has(&["metal", "iron", "steel", "anvil", "forge"]) {
+            Some(Surface::Metal)
+        } else if has(&["glass", "window"]) {
+            Some(Surface::Glass)
+        } else if has(&["wood", "plank", "box", "crate"]) {
+            Some(Surface::Wood)
+        } else if has(&["concrete", "stone", "wall", "land", "road"]) {
+            Some(Surface::Concrete)
+        } else {
+            None
+        }
+    }
+
+    /// Playback speeds of the sounds of the bank. Until each surface gets its own recordings,
+    /// the banks are pitched variations of `hit.ogg`.
+    pub fn sound_bank(&self) -> &'static [f32] {
+        match self {
+            Surface::Metal => &[1.0, 1.08, 0.94],
+            Surface::Concrete => &[0.7, 0.75, 0.66],
+            Surface::Glass => &[1.6, 1.75, 1.5],
+            Surface::Wood => &[0.5, 0.55, 0.46],
+        }
+    }
+
+    fn particle_color(&self) -> Color {
+        match self {
+            Surface::Metal => Color::rgb(4.0, 1.6, 0.4),
+            Surface::Concrete => Color::rgb(0.55, 0.53, 0.5),
+            Surface::Glass => Color::rgba(0.7, 0.9, 1.0, 0.6),
+            Surface::Wood => Color::rgb(0.45, 0.3, 0.15),
+        }
+    }
+
+    /// Initial speed of the particles in m/s, and their lifetime in seconds.
+    fn particle_motion(&self) -> (f32, f32) {
+        match self {
+            Surface::Metal => (6.0, 0.4),
+            Surface::Concrete => (2.0, 0.8),
+            Surface::Glass => (4.0, 0.6),
+            Surface::Wood => (3.0, 0.7),
+        }
+    }
+}
+
+/// The surface of a scene mesh, from its node names first and its material name otherwise.
+pub fn scene_surface<'a>(
+    node_names: impl IntoIterator<Item = &'a str>,
+    material_name: Option<&'a str>,
+) -> Surface {
+    node_names
+        .into_iter()
+        .chain(material_name)
+        .find_map(Surface::from_name)
+        .unwrap_or_default()
+}
+
+#[derive(Resource)]
+pub struct SurfaceAssets {
+    particle: Handle<Mesh>,
+    materials: Vec<(Surface, Handle<StandardMaterial>)>,
+}
+
+#[derive(Component)]
+pub struct Particle {
+    velocity: Vec3,
+    lifetime: f32,
+    remaining: f32,
+}
+
+pub fn setup_surface_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(SurfaceAssets {
+        particle: meshes.add(Mesh::from(shape::Cube { size: 0.06 })),
+        materials: Surface::ALL
+            .iter()
+            .map(|surface| {
+                let color = surface.particle_color();
+                let material = materials.add(StandardMaterial {
+                    base_color: color,
+                    // sparks glow with the bloom
+                    emissive: if *surface == Surface::Metal {
+                        color
+                    } else {
+                        Color::BLACK
+                    },
+                    alpha_mode: if color.a() < 1.0 {
+                        AlphaMode::Blend
+                    } else {
+                        AlphaMode::Opaque
+                    },
+                    unlit: *surface == Surface::Metal,
+                    ..default()
+                });
+                (*surface, material)
+            })
+            .collect(),
+    });
+}
+
+/// Spawns particles flying out of `point`, more of them for louder impacts.
+pub fn spawn_impact_particles(
+    commands: &mut Commands,
+    surface_assets: &SurfaceAssets,
+    surface: Surface,
+    point: Vec3,
+    volume: f32,
+) {
+    let Some((_, material)) = surface_assets
+        .materials
+        .iter()
+        .find(|(material_surface, _)| *material_surface == surface)
+    else {
+        return;
+    };
+    let (speed, lifetime) = surface.particle_motion();
+    let count = 4 + (volume * 12.0) as usize;
+    for i in 0..count {
+        // spread evenly over the upper hemisphere, along a golden angle spiral
+        let up = 1.0 - (i as f32 + 0.5) / count as f32;
+        let angle = i as f32 * PI * (3.0 - 5.0f32.sqrt());
+        let radius = (1.0 - up * up).sqrt();
+        let direction = Vec3::new(radius * angle.cos(), up, radius * angle.sin());
+        commands.spawn((
+            PbrBundle {
+                mesh: surface_assets.particle.clone(),
+                material: material.clone(),
+                transform: Transform::from_translation(point),
+                ..default()
+            },
+            Particle {
+                velocity: direction * speed * (0.5 + volume),
+                lifetime,
+                remaining: lifetime,
+            },
+            Transient::default(),
+        ));
+    }
+}
+
+pub fn update_particles(
+    mut commands: Commands,
+    mut particle_query: Query<(Entity, &mut Particle, &mut Transform)>,
+    input: Res<FrameInput>,
+) {
+    for (ent, mut particle, mut tr) in particle_query.iter_mut() {
+        particle.remaining -= input.dt;
+        if particle.remaining <= 0.0 {
+            commands.entity(ent).despawn();
+            continue;
+        }
+        particle.velocity.y -= 9.81 * input.dt;
+        tr.translation += particle.velocity * input.dt;
+        tr.scale = Vec3::splat(particle.remaining / particle.lifetime);
+    }
+}
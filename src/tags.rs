@@ -0,0 +1,177 @@
+use bevy::{gltf::GltfExtras, prelude::*};
+use serde::Deserialize;
+
+use crate::{colliders::ColliderShape, surface::Surface};
+
+/// Force in newtons the anvil has to hit a breakable with, unless its tags say otherwise.
+const DEFAULT_BREAKABLE_THRESHOLD: f32 = 5000.0;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SpawnPoint {
+    Player,
+    Anvil,
+}
+
+/// Tags of a scene node, from the custom properties Blender exports as glTF `extras`, e.g.
+/// `{"objective": 0}`, `{"breakable": 8000, "surface": "glass"}` or
+/// `{"collider": "convex_hull", "dynamic": true}`.
+///
+/// The older naming conventions, `Objective0` and `Breakable_8000`, are still understood.
+#[derive(Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneTags {
+    pub spawn: Option<SpawnPoint>,
+    /// Number of the customer, indexes the powerups of the level.
+    pub objective: Option<u32>,
+    pub kill_volume: bool,
+    /// Minimum force in newtons to break the mesh.
+    pub breakable: Option<f32>,
+    pub collider: Option<ColliderShape>,
+    /// Makes the mesh a prop moved by the physics.
+    pub dynamic: bool,
+    pub surface: Option<Surface>,
+    /// Order of the checkpoint in the level.
+    pub checkpoint: Option<u32>,
+}
+
+/// Parses the number in names like `Objective2` or `Breakable_8000.001`.
+fn name_number<T: std::str::FromStr>(name: &str, rest: &str) -> Result<Option<T>, String> {
+    // skip the `.001` suffixes added by Blender
+    let number = rest.split('.').next().unwrap_or_default();
+    if number.is_empty() {
+        return Ok(None);
+    }
+    number
+        .parse()
+        .map(Some)
+        .map_err(|_| format!("{:?} doesn't end with a number", name))
+}
+
+impl SceneTags {
+    fn from_name(name: &str) -> Result<SceneTags, String> {
+        let mut tags = SceneTags::default();
+        if let Some(rest) = name.strip_prefix("Objective") {
+            tags.objective =
+                Some(name_number(name, rest)?.ok_or(format!("{:?} is missing its number", name))?);
+        } else if let Some(rest) = name.strip_prefix("Breakable") {
+            let threshold = match rest.strip_prefix('_') {
+                Some(rest) => name_number(name, rest)?,
+                None => None,
+            };
+            tags.breakable = Some(threshold.unwrap_or(DEFAULT_BREAKABLE_THRESHOLD));
+        }
+        Ok(tags)
+    }
+
+    fn from_extras(extras: &GltfExtras) -> Result<SceneTags, String> {
+        serde_json::from_str(&extras.value).map_err(|err| err.to_string())
+    }
+
+    /// Combines the tags, those of `other` taking precedence.
+    pub fn merge(self, other: SceneTags) -> SceneTags {
+        SceneTags {
+            spawn: other.spawn.or(self.spawn),
+            objective: other.objective.or(self.objective),
+            kill_volume: other.kill_volume || self.kill_volume,
+            breakable: other.breakable.or(self.breakable),
+            collider: other.collider.or(self.collider),
+            dynamic: other.dynamic || self.dynamic,
+            surface: other.surface.or(self.surface),
+            checkpoint: other.checkpoint.or(self.checkpoint),
+        }
+    }
+
+    /// Tags of an entity of a glTF scene, logging the malformed ones instead of failing the level.
+    pub fn of_entity(name: Option<&Name>, extras: Option<&GltfExtras>) -> SceneTags {
+        let label = name.map(|name| name.as_str()).unwrap_or("<unnamed>");
+        let from_name = name
+            .map(|name| SceneTags::from_name(name))
+            .unwrap_or(Ok(default()));
+        let from_extras = extras.map(SceneTags::from_extras).unwrap_or(Ok(default()));
+        let from_name = from_name.unwrap_or_else(|err| {
+            error!("Ignoring the name tags of {}: {}", label, err);
+            default()
+        });
+        let from_extras = from_extras.unwrap_or_else(|err| {
+            error!("Ignoring the malformed extras of {}: {}", label, err);
+            default()
+        });
+        from_name.merge(from_extras)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extras(value: &str) -> GltfExtras {
+        GltfExtras {
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn objective_names() {
+        assert_eq!(
+            SceneTags::from_name("Objective2").unwrap().objective,
+            Some(2)
+        );
+        // duplicated in Blender
+        assert_eq!(
+            SceneTags::from_name("Objective2.001").unwrap().objective,
+            Some(2)
+        );
+        assert!(SceneTags::from_name("Objective").is_err());
+        assert!(SceneTags::from_name("ObjectiveX").is_err());
+    }
+
+    #[test]
+    fn breakable_names() {
+        assert_eq!(
+            SceneTags::from_name("Breakable_8000").unwrap().breakable,
+            Some(8000.0)
+        );
+        assert_eq!(
+            SceneTags::from_name("Breakable").unwrap().breakable,
+            Some(DEFAULT_BREAKABLE_THRESHOLD)
+        );
+        assert!(SceneTags::from_name("Breakable_heavy").is_err());
+    }
+
+    #[test]
+    fn untagged_names() {
+        assert_eq!(
+            SceneTags::from_name("Cube.003").unwrap(),
+            SceneTags::default()
+        );
+    }
+
+    #[test]
+    fn extras_tags() {
+        let tags =
+            SceneTags::from_extras(&extras(r#"{"collider": "convex_hull", "dynamic": true}"#))
+                .unwrap();
+        assert_eq!(tags.collider, Some(ColliderShape::ConvexHull));
+        assert!(tags.dynamic);
+        let tags =
+            SceneTags::from_extras(&extras(r#"{"objective": 1, "spawn": "anvil"}"#)).unwrap();
+        assert_eq!(tags.objective, Some(1));
+        assert_eq!(tags.spawn, Some(SpawnPoint::Anvil));
+    }
+
+    #[test]
+    fn malformed_extras() {
+        assert!(SceneTags::from_extras(&extras(r#"{"objective": "first"}"#)).is_err());
+        assert!(SceneTags::from_extras(&extras(r#"{"objectve": 1}"#)).is_err());
+        assert!(SceneTags::from_extras(&extras(r#"{"collider": "sphere"}"#)).is_err());
+        assert!(SceneTags::from_extras(&extras("{\"objective\": 1")).is_err());
+    }
+
+    #[test]
+    fn extras_take_precedence() {
+        let from_name = SceneTags::from_name("Objective0").unwrap();
+        let from_extras = SceneTags::from_extras(&extras(r#"{"objective": 3}"#)).unwrap();
+        assert_eq!(from_name.merge(from_extras).objective, Some(3));
+    }
+}
@@ -0,0 +1,64 @@
+use bevy::prelude::*;
+
+use crate::{level::CurrentLevel, replay::FrameInput, save::SaveData};
+
+/// Times a level from the first time it is played, across the reloads after each delivery.
+#[derive(Resource, Default, Clone, Debug)]
+pub struct RunTimer {
+    pub elapsed: f32,
+    pub running: bool,
+    /// Elapsed time at each delivery.
+    pub splits: Vec<f32>,
+}
+
+#[derive(Component)]
+pub struct RunTimerText;
+
+pub fn format_time(secs: f32) -> String {
+    let minutes = (secs / 60.0).floor();
+    format!("{}:{:04.1}", minutes, secs - minutes * 60.0)
+}
+
+fn format_delta(delta: f32) -> String {
+    if delta < 0.0 {
+        format!("-{:.1}", -delta)
+    } else {
+        format!("+{:.1}", delta)
+    }
+}
+
+pub fn start_run_timer(mut run_timer: ResMut<RunTimer>) {
+    if !run_timer.running {
+        *run_timer = RunTimer {
+            running: true,
+            ..default()
+        };
+    }
+}
+
+pub fn stop_run_timer(mut run_timer: ResMut<RunTimer>) {
+    run_timer.running = false;
+}
+
+pub fn tick_run_timer(mut run_timer: ResMut<RunTimer>, input: Res<FrameInput>) {
+    run_timer.elapsed += input.dt;
+}
+
+pub fn update_run_timer_text(
+    mut text_query: Query<&mut Text, With<RunTimerText>>,
+    run_timer: Res<RunTimer>,
+    save: Res<SaveData>,
+    current_level: Res<CurrentLevel>,
+) {
+    let best_splits = save.best_splits.get(&current_level.info.name);
+    let mut text = format_time(run_timer.elapsed);
+    for (i, split) in run_timer.splits.iter().enumerate() {
+        text += &format!("\n{}: {}", i + 1, format_time(*split));
+        if let Some(best) = best_splits.and_then(|best_splits| best_splits.get(i)) {
+            text += &format!(" ({})", format_delta(split - best));
+        }
+    }
+    for mut timer_text in text_query.iter_mut() {
+        timer_text.sections[0].value = text.clone();
+    }
+}